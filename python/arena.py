@@ -1,4 +1,5 @@
 import logging
+from typing import Callable, Optional
 
 from numpy.typing import NDArray
 from numpy import float32
@@ -18,7 +19,8 @@ class Arena:
     def __init__(self):
         pass
 
-    def play_game(self, sp: SelfPlayer, net1: NNetWrapper, net2: NNetWrapper) -> NDArray[float32]:
+    # net1かnet2がNoneの手番は、Rust側のUCTなどが指すので予測しない
+    def play_game(self, sp: SelfPlayer, net1: Optional[NNetWrapper], net2: Optional[NNetWrapper]) -> NDArray[float32]:
         turn = 0
         cur_player = -1
         while True:
@@ -30,15 +32,15 @@ class Arena:
             elif rnum == 1:
                 boards = sp.get_boards_for_prediction(cur_player)
                 if cur_player == 1:
-                    pis, win_rates = net1.predict(boards)
+                    pis, win_rates, scores = net1.predict(boards)  # type: ignore
                 else:
-                    pis, win_rates = net2.predict(boards)
-                sp.receive_prediction(pis, win_rates, cur_player)
+                    pis, win_rates, scores = net2.predict(boards)  # type: ignore
+                sp.receive_prediction(pis, win_rates, cur_player, scores)
             elif rnum == 2:
                 results = sp.get_results_for_counting()
                 return results
 
-    def play_game_and_count_wons(self, sp: SelfPlayer, net1: NNetWrapper, net2: NNetWrapper) -> tuple[int, int, int]:
+    def play_game_and_count_wons(self, sp: SelfPlayer, net1: Optional[NNetWrapper], net2: Optional[NNetWrapper]) -> tuple[int, int, int]:
         array = self.play_game(sp, net1, net2)
         return (np.count_nonzero(array == 1.0), np.count_nonzero(array == -1.0), np.count_nonzero(array == 0.0))
 
@@ -54,3 +56,23 @@ class Arena:
             f"NNET WIN RATE FIRST {n_won/(n_won+p_won+draws)} SECOND {n_won2/(n_won2+p_won2+draws2)}")
 
         return n_won + n_won2, p_won + p_won2, draws + draws2
+
+    # create_sp(opponent_player)でSelfPlayerを作り、nnetを先手と後手で一回ずつ戦わせる
+    def play_games_vs(self, create_sp: Callable[[int], SelfPlayer], nnet: NNetWrapper, name: str) -> tuple[int, int, int]:
+
+        n_won, o_won, draws = self.play_game_and_count_wons(
+            create_sp(-1), nnet, None)
+
+        o_won2, n_won2, draws2 = self.play_game_and_count_wons(
+            create_sp(1), None, nnet)
+
+        log.info(
+            f"NNET WIN RATE VS {name} FIRST {n_won/(n_won+o_won+draws)} SECOND {n_won2/(n_won2+o_won2+draws2)}")
+
+        return n_won + n_won2, o_won + o_won2, draws + draws2
+
+    def play_games_vs_uct(self, pc: PyCommunicator, nnet: NNetWrapper, num_sims: int = 1000, num_rollouts: int = 1) -> tuple[int, int, int]:
+        return self.play_games_vs(lambda p: pc.create_self_player_vs_uct(p, num_sims, num_rollouts), nnet, f"UCT-{num_sims}")
+
+    def play_games_vs_alpha_beta(self, pc: PyCommunicator, nnet: NNetWrapper, depth: int, time_ms: int = 0) -> tuple[int, int, int]:
+        return self.play_games_vs(lambda p: pc.create_self_player_vs_alpha_beta(p, depth, time_ms), nnet, f"ALPHABETA-{depth}-{time_ms}ms")
@@ -23,21 +23,33 @@ def main():
         file1 = sys.argv[1]
         file2 = sys.argv[2]
     except IndexError:
-        log.info('you need two files to compare')
+        log.info('you need two files to compare (or a file and "uct" / "alphabeta")')
         return
 
-    pc = PyCommunicator(args.is_release)
+    pc = PyCommunicator(args.is_release, args.engine_config)
     net1 = NNetWrapper(pc, args)
     net2 = NNetWrapper(pc, args)
 
     log.info('loading files...')
     if file1 != "none":
         net1.load_checkpoint(folder="target", filename=file1)
-    if file2 != "none":
+    if file2 not in ["none", "uct", "alphabeta"]:
         net2.load_checkpoint(folder="target", filename=file2)
 
     arena = Arena()
 
+    if file2 == "uct":
+        nwins, uwins, draws = arena.play_games_vs_uct(pc, net1, 1000)
+        log.info("NNET/UCT-1000 WINS : %d / %d ; DRAWS : %d" %
+                 (nwins, uwins, draws))
+        return
+
+    if file2 == "alphabeta":
+        nwins, awins, draws = arena.play_games_vs_alpha_beta(pc, net1, 6)
+        log.info("NNET/ALPHABETA-6 WINS : %d / %d ; DRAWS : %d" %
+                 (nwins, awins, draws))
+        return
+
     p1wins, p2wins, draws = arena.play_games(pc, net1, net2)
 
     log.info("P1/P2 WINS : %d / %d ; DRAWS : %d" %
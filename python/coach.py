@@ -1,5 +1,6 @@
 from collections import deque
 import logging
+import os
 
 
 from .mcts_args import MctsArgs
@@ -28,8 +29,12 @@ class Coach:
     def learn(self):
         for i in range(1_000_000):
             print(f"iter {i+1}")
-            train_examples = self.make_train_example(
-                self.pc.create_self_player(1))
+            sp = self.pc.create_self_player(1)
+            # 落ちても自己対戦のデータが残るように、ファイルにも書く
+            os.makedirs(self.args.checkpoint, exist_ok=True)
+            sp.write_examples(os.path.join(
+                self.args.checkpoint, f"examples_{i}.bin"))
+            train_examples = self.make_train_example(sp)
 
             train_examples = [te for tes in train_examples for te in self.get_symmetries(
                 tes)]
@@ -94,19 +99,37 @@ class Coach:
                     )
 
     def make_train_example(self, sp: SelfPlayer) -> list[TrainExample]:
-
+        batches = 0
         while True:
             rnum = sp.prepare_next(0)
 
             if rnum == 0:
                 continue
             elif rnum == 1:
-                pis, win_rates = self.nnet.predict(
+                sp.set_network_version(self.nnet.version)
+                pis, win_rates, scores = self.nnet.predict(
                     sp.get_boards_for_prediction(0))
-                sp.receive_prediction(pis, win_rates, 0)
+                sp.receive_prediction(pis, win_rates, 0, scores)
+                batches += 1
+                if batches % 1000 == 0:
+                    self.log_stats(sp)
             elif rnum == 2:
+                self.log_stats(sp)
+                hits, misses = sp.get_eval_cache_stats()
+                log.info(f"EVAL CACHE HITS {int(hits)} MISSES {int(misses)}")
+                resigned, control, false_positives, rate = sp.get_resign_stats()
+                log.info(
+                    f"RESIGNED {int(resigned)} CONTROL {int(control)} FALSE POSITIVES {int(false_positives)} RATE {rate:.3f}")
+                error = sp.get_example_write_error()
+                if error is not None:
+                    log.warning(f"FAILED TO WRITE EXAMPLES: {error}")
                 return sp.get_train_examples()
 
+    def log_stats(self, sp: SelfPlayer):
+        games, in_flight, ply, positions, requests, hits, sims_per_sec, fill = sp.get_stats()
+        log.info(
+            f"GAMES {int(games)} IN FLIGHT {int(in_flight)} PLY {ply:.1f} POSITIONS {int(positions)} REQUESTS {int(requests)} CACHE HITS {int(hits)} SIMS/S {sims_per_sec:.0f} FILL {fill:.2f}")
+
     def get_checkpoint_file(self, iteration: int) -> str:
         return "checkpoint_" + str(iteration) + ".pth.tar"
 
@@ -126,7 +149,7 @@ class Coach:
                     newB = np.fliplr(newB)
                     newPi = np.fliplr(newPi)
                 l.append(TrainExample(newB, train_example.cur_player, np.array(
-                    list(newPi.ravel()) + [pi[-1]]), train_example.v))
+                    list(newPi.ravel()) + [pi[-1]]), train_example.v, train_example.score, train_example.weight))
         return l
 
     # def save_train_examples(self, iteration: int):
@@ -0,0 +1,75 @@
+from ctypes import c_void_p, c_char_p, c_size_t, c_uint64, c_bool, POINTER, CDLL
+from typing import Sequence
+
+from .intf_carray import CArray
+from .train_example import TrainExample
+
+
+# SelfPlayer.write_examplesで書いた教師データのファイルを読む
+class ExampleReader:
+    def __init__(self, lib: CDLL, p: c_void_p):
+        self.lib = lib
+        self.p = p
+
+    def __del__(self):
+        self.lib.destroy_example_reader(self.p)
+
+    # 全ファイルの教師データの数
+    def __len__(self) -> int:
+        return self.lib.example_reader_len(self.p)
+
+    # 最初から読み直す。シャッフルするならチャンクの順も変える
+    def reset(self):
+        self.lib.example_reader_reset(self.p)
+
+    # 次のbatch_size個。読み終わったら空のリスト
+    def next_batch(self, batch_size: int) -> list[TrainExample]:
+        if self.lib.example_reader_next_batch(self.p, batch_size) == 0:
+            return []
+        return self.__batch()
+
+    # 全ファイルの同じ局面の教師データをまとめて返す。weightはまとめた数
+    def dedup_all(self, symmetry: bool = False) -> list[TrainExample]:
+        if self.lib.example_reader_dedup_all(self.p, symmetry) == 0:
+            return []
+        return self.__batch()
+
+    def __batch(self) -> list[TrainExample]:
+        pis = CArray(self.lib, self.lib.example_reader_get_pis(self.p)).to_numpy()
+        boards = CArray(self.lib, self.lib.example_reader_get_boards(self.p)).to_numpy()
+        players = CArray(self.lib, self.lib.example_reader_get_players(self.p)).to_numpy()
+        value_targets = CArray(self.lib, self.lib.example_reader_get_value_targets(self.p)).to_numpy()
+        scores = CArray(self.lib, self.lib.example_reader_get_scores(self.p)).to_numpy()
+        weights = CArray(self.lib, self.lib.example_reader_get_weights(self.p)).to_numpy()
+        return [TrainExample(board, player, pi, v, score, w) for pi, board, player, v, score, w in zip(pis, boards, players, value_targets, scores, weights)]
+
+    def read_all(self) -> list[TrainExample]:
+        self.reset()
+        examples: list[TrainExample] = []
+        while True:
+            batch = self.next_batch(4096)
+            if not batch:
+                return examples
+            examples.extend(batch)
+
+
+def encode_paths(paths: Sequence[str]) -> bytes:
+    return '\n'.join(paths).encode('utf-8')
+
+
+def define_example_file_funcs(lib: CDLL):
+    lib.create_example_reader.argtypes = [
+        c_char_p, c_size_t, c_uint64, POINTER(c_void_p)]
+    lib.create_example_reader.restype = POINTER(c_void_p)
+    lib.destroy_example_reader.argtypes = [POINTER(c_void_p)]
+    lib.example_reader_len.argtypes = [POINTER(c_void_p)]
+    lib.example_reader_len.restype = c_size_t
+    lib.example_reader_reset.argtypes = [POINTER(c_void_p)]
+    lib.example_reader_next_batch.argtypes = [POINTER(c_void_p), c_size_t]
+    lib.example_reader_next_batch.restype = c_size_t
+    lib.example_reader_dedup_all.argtypes = [POINTER(c_void_p), c_bool]
+    lib.example_reader_dedup_all.restype = c_size_t
+    for name in ['pis', 'boards', 'players', 'value_targets', 'scores', 'weights']:
+        f = getattr(lib, f'example_reader_get_{name}')
+        f.argtypes = [POINTER(c_void_p)]
+        f.restype = POINTER(c_void_p)
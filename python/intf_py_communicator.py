@@ -1,13 +1,20 @@
-from ctypes import c_void_p, c_size_t, POINTER, CDLL
+from ctypes import c_void_p, c_char_p, c_size_t, c_uint64, c_float, c_double, c_bool, POINTER, CDLL
+from typing import Optional, Union
 import ctypes
+import json
 import numpy as np
 
 from .intf_self_player import SelfPlayer, define_self_player_funcs
-from .intf_carray import define_carray_funcs
+from .intf_carray import CArray, define_carray_funcs
+from .intf_example_file import ExampleReader, define_example_file_funcs, encode_paths
+from numpy.typing import NDArray
+from numpy import float32
 
 
 class PyCommunicator:
-    def __init__(self, is_release: bool):
+    # configはRust側のEngineConfig。dictならJSONにして渡し、strならJSONかTOMLのファイルのパスとして読む
+    def __init__(self, is_release: bool, config: Optional[Union[dict, str]] = None):
+        self.p = None
         if is_release:
             self.lib = ctypes.cdll.LoadLibrary(
                 'target/release/rust_othello_alphazero.dll')
@@ -17,14 +24,103 @@ class PyCommunicator:
         define_py_communicator_funcs(self.lib)
         define_self_player_funcs(self.lib)
         define_carray_funcs(self.lib)
-        self.p = self.lib.create_py_communicator()
+        define_example_file_funcs(self.lib)
+        if config is None:
+            self.p = self.lib.create_py_communicator()
+        else:
+            if isinstance(config, dict):
+                text = json.dumps(config)
+            else:
+                with open(config, encoding='utf-8') as f:
+                    text = f.read()
+            error = c_void_p()
+            p = self.lib.create_py_communicator_with_config(
+                text.encode('utf-8'), ctypes.byref(error))
+            if not p:
+                message = ctypes.string_at(error).decode('utf-8')
+                self.lib.destroy_c_string(error)
+                raise ValueError(f"invalid engine config: {message}")
+            self.p = p
 
     def __del__(self):
-        self.lib.destroy_py_communicator(self.p)
+        if self.p:
+            self.lib.destroy_py_communicator(self.p)
 
     def create_self_player(self, player_mode: int) -> SelfPlayer:
         return SelfPlayer(self.lib, self.lib.create_self_player(self.p, player_mode))
 
+    # 試合を止めずに続け、終わった試合の教師データをcapacity個までリプレイバッファに溜める
+    # 学習のミニバッチはSelfPlayer.sample_replayで取り出す。予測の前にSelfPlayer.set_network_versionを呼ぶ
+    def create_continuous_self_player(self, player_mode: int, capacity: int) -> SelfPlayer:
+        return SelfPlayer(self.lib, self.lib.create_continuous_self_player(self.p, player_mode, capacity))
+
+    # SelfPlayer.write_examplesで書いたファイルを読む
+    # shuffle_chunksが0ならファイルの順に、1以上ならチャンクの順をシャッフルし、その数のチャンクを混ぜて返す
+    def open_example_reader(self, paths: list[str], shuffle_chunks: int = 0, seed: int = 0) -> ExampleReader:
+        error = c_void_p()
+        p = self.lib.create_example_reader(
+            encode_paths(paths), shuffle_chunks, seed, ctypes.byref(error))
+        if not p:
+            message = ctypes.string_at(error).decode('utf-8')
+            self.lib.destroy_c_string(error)
+            raise IOError(f"cannot open train examples: {message}")
+        return ExampleReader(self.lib, p)
+
+    # uct_player(1か-1)の手番はNNを使わず、ランダムプレイアウトのUCTが指す
+    def create_self_player_vs_uct(self, uct_player: int, num_sims: int, num_rollouts: int = 1) -> SelfPlayer:
+        return SelfPlayer(self.lib, self.lib.create_self_player_vs_uct(self.p, uct_player, num_sims, num_rollouts))
+
+    # boardの局面(N×N、1が黒、-1が白)をplayerの手番で、全スレッドで共有する木で探索する
+    def create_shared_search(self, board: NDArray[float32], player: int, num_sims: int) -> SelfPlayer:
+        c_board = CArray.from_numpy(self.lib, board.astype(np.float32))
+        return SelfPlayer(self.lib, self.lib.create_shared_search(self.p, c_board.p, player, num_sims))
+
+    # alpha_beta_player(1か-1)の手番はアルファベータ探索が指す。depthかtime_msの0は制限なし
+    def create_self_player_vs_alpha_beta(self, alpha_beta_player: int, depth: int, time_ms: int = 0) -> SelfPlayer:
+        return SelfPlayer(self.lib, self.lib.create_self_player_vs_alpha_beta(self.p, alpha_beta_player, depth, time_ms))
+
+    # 0: PUCT, 1: Gumbel。それ以外ならFalse
+    def set_root_search(self, root_search: int) -> bool:
+        return self.lib.py_communicator_set_root_search(self.p, root_search)
+
+    # 0: z, 1: q, 2: (z+q)/2, 3: 手数で線形補間。それ以外ならFalse
+    def set_value_target(self, value_target: int) -> bool:
+        return self.lib.py_communicator_set_value_target(self.p, value_target)
+
+    # kind 0: num_mcts_sims回, 1: 一手value秒, 2: ルートの訪問回数value回,
+    # 3: 持ち時間value秒で一手ごとにincrement秒加算。kindが不明か値が不正ならFalse
+    def set_search_budget(self, kind: int, value: float = 0.0, increment: float = 0.0) -> bool:
+        return self.lib.py_communicator_set_search_budget(self.p, kind, value, increment)
+
+    def set_early_stop(self, early_stop: bool):
+        self.lib.py_communicator_set_early_stop(self.p, early_stop)
+
+    # forced_playouts: ルートの子を最低sqrt(k * P * N)回訪問する(PUCTのみ)
+    # policy_target_pruning: 方策の教師データから強制された訪問を取り除く。kが正でなければFalse
+    def set_forced_playouts(self, forced_playouts: bool, k: float = 2.0, policy_target_pruning: bool = True) -> bool:
+        return self.lib.py_communicator_set_forced_playouts(self.p, forced_playouts, k, policy_target_pruning)
+
+    # 予測した石差にweightを掛けてQに加え、1 + weightで割る。0ならスコアを使わない。負ならFalse
+    def set_score_utility_weight(self, weight: float) -> bool:
+        return self.lib.py_communicator_set_score_utility_weight(self.p, weight)
+
+    # ルートの探索Qがthresholdを下回ったら投了する。-1.0以下なら投了しない
+    # disabled_fractionの割合の試合では投了を禁止し、誤投了率を調べる。値が不正ならFalse
+    def set_resign(self, threshold: float, disabled_fraction: float) -> bool:
+        return self.lib.py_communicator_set_resign(self.p, threshold, disabled_fraction)
+
+    # 同時に進める試合の数、NNに一度に渡す盤面の最大数、一つのSelfPlayerで行う試合の数。どれかが0ならFalse
+    def set_self_play(self, concurrent_games: int, batch_size: int, games_per_generation: int) -> bool:
+        return self.lib.py_communicator_set_self_play(self.p, concurrent_games, batch_size, games_per_generation)
+
+    # min_batch_size個の盤面が揃うか、timeout_usマイクロ秒待ったらバッチを返す。min_batch_sizeが0ならFalse
+    def set_batch_dispatch(self, min_batch_size: int, timeout_us: int) -> bool:
+        return self.lib.py_communicator_set_batch_dispatch(self.p, min_batch_size, timeout_us)
+
+    # SelfPlayerを作るたびにseedは1ずつ進む
+    def set_seed(self, seed: int):
+        self.lib.py_communicator_set_seed(self.p, seed)
+
     def size_y(self) -> int:
         return self.lib.size_y()
 
@@ -44,6 +140,37 @@ class PyCommunicator:
 def define_py_communicator_funcs(lib: CDLL):
     lib.create_py_communicator.restype = POINTER(c_void_p)
     lib.destroy_py_communicator.argtypes = [POINTER(c_void_p)]
+    lib.create_py_communicator_with_config.argtypes = [
+        c_char_p, POINTER(c_void_p)]
+    lib.create_py_communicator_with_config.restype = POINTER(c_void_p)
+    lib.py_communicator_set_root_search.argtypes = [
+        POINTER(c_void_p), c_size_t]
+    lib.py_communicator_set_root_search.restype = c_bool
+    lib.py_communicator_set_value_target.argtypes = [
+        POINTER(c_void_p), c_size_t]
+    lib.py_communicator_set_value_target.restype = c_bool
+    lib.py_communicator_set_search_budget.argtypes = [
+        POINTER(c_void_p), c_size_t, c_double, c_double]
+    lib.py_communicator_set_search_budget.restype = c_bool
+    lib.py_communicator_set_early_stop.argtypes = [
+        POINTER(c_void_p), c_bool]
+    lib.py_communicator_set_forced_playouts.argtypes = [
+        POINTER(c_void_p), c_bool, c_float, c_bool]
+    lib.py_communicator_set_forced_playouts.restype = c_bool
+    lib.py_communicator_set_score_utility_weight.argtypes = [
+        POINTER(c_void_p), c_float]
+    lib.py_communicator_set_score_utility_weight.restype = c_bool
+    lib.py_communicator_set_resign.argtypes = [
+        POINTER(c_void_p), c_float, c_float]
+    lib.py_communicator_set_resign.restype = c_bool
+    lib.py_communicator_set_self_play.argtypes = [
+        POINTER(c_void_p), c_size_t, c_size_t, c_size_t]
+    lib.py_communicator_set_self_play.restype = c_bool
+    lib.py_communicator_set_batch_dispatch.argtypes = [
+        POINTER(c_void_p), c_size_t, c_uint64]
+    lib.py_communicator_set_batch_dispatch.restype = c_bool
+    lib.py_communicator_set_seed.argtypes = [
+        POINTER(c_void_p), c_uint64]
     lib.batch_size.restype = c_size_t
     lib.size_x.restype = c_size_t
     lib.size_y.restype = c_size_t
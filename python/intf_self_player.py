@@ -1,4 +1,6 @@
-from ctypes import c_void_p, c_size_t, POINTER, CDLL
+from ctypes import c_void_p, c_char_p, c_size_t, c_uint64, c_float, c_bool, POINTER, CDLL
+import ctypes
+from typing import Optional
 from numpy.typing import NDArray
 from numpy import float32
 
@@ -14,7 +16,11 @@ class SelfPlayer:
     def __del__(self):
         self.lib.destroy_self_player(self.p)
 
-    #  NスレッドでN個の試合を同時にシミュレーションしている。一手進めて盤面を返す
+    # 進行中の試合をすべて打ち切る。その後のprepare_nextは終わっていた試合だけで2を返す
+    def cancel(self):
+        self.lib.self_player_cancel(self.p)
+
+    #  concurrent_games個の試合を同時にシミュレーションしている。一手進めて盤面を返す
     #
     #  最初の指し手は必ずplayer1とする。
     #
@@ -26,15 +32,35 @@ class SelfPlayer:
     #  戻り値:
     #  0: まだ準備が出来ていない
     #  1: 盤面の準備が出来た
-    #  2: すべての試合が既に終わっていて、トレーニング用のデータの準備が出来た
+    #  2: games_per_generation個の試合が終わっていて、トレーニング用のデータの準備が出来た
     def prepare_next(self, player: int) -> int:
         return self.lib.self_player_prepare_next(self.p, player)
 
-    # BATCH_SIZE * BOARD_SIZE
+    # 評価が必要な盤面の数(最大batch_size) * BOARD_SIZE
     def get_boards_for_prediction(self, player: int) -> NDArray[float32]:
         carray = self.lib.self_player_get_boards_for_prediction(self.p, player)
         return CArray(self.lib, carray).to_numpy()
 
+    # 直前のget_boards_for_predictionの各行のthread_id
+    def get_batch_thread_ids(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_batch_thread_ids(self.p)).to_numpy()
+
+    def clear_eval_cache(self):
+        self.lib.self_player_clear_eval_cache(self.p)
+
+    # 予測に使うNNの重みの版(NNetWrapper.version)。前と違えば予測結果のキャッシュを捨てる
+    def set_network_version(self, version: int):
+        self.lib.self_player_set_network_version(self.p, version)
+
+    # [キャッシュヒット数, ミス数]
+    def get_eval_cache_stats(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_eval_cache_stats(self.p)).to_numpy()
+
+    # [終わった試合数, 進行中の試合数, 平均手数, 評価した盤面数, NNへの要求数, キャッシュヒット数,
+    #  1秒あたりのシミュレーション数, バッチの平均充填率]
+    def get_stats(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_stats(self.p)).to_numpy()
+
     # BATCH_SIZE * MOVE_LEN
     def get_pis_for_training(self) -> NDArray[float32]:
         return CArray(self.lib, self.lib.self_player_get_pis_for_training(self.p)).to_numpy()
@@ -51,27 +77,118 @@ class SelfPlayer:
     def get_results_for_training(self) -> NDArray[float32]:
         return CArray(self.lib, self.lib.self_player_get_results_for_training(self.p)).to_numpy()
     
+    # そのスレッドが最後に探索したルートの解析結果。まだなければNone
+    # moves: 合法手ごとの[action, visits, q, prior, ucb]
+    # pv: 読み筋のaction
+    def get_analysis(self, thread_id: int) -> Optional[tuple[NDArray[float32], NDArray[float32], float]]:
+        moves = self.lib.self_player_get_analysis_moves(self.p, thread_id)
+        if not moves:
+            return None
+        pv = self.lib.self_player_get_analysis_pv(self.p, thread_id)
+        root_value = self.lib.self_player_get_analysis_root_value(self.p, thread_id)
+        return (CArray(self.lib, moves).to_numpy(), CArray(self.lib, pv).to_numpy(), root_value)
+
+    # thread_idのスレッドが次に手を決めたとき、そのルートの探索木を書き出させる
+    def request_tree_export(self, thread_id: int, max_depth: int, min_visits: int = 0) -> bool:
+        return self.lib.self_player_request_tree_export(self.p, thread_id, max_depth, min_visits)
+
+    # format 0: DOT, 1: JSON。まだ書き出されていなければNone
+    def get_tree_export(self, thread_id: int, format: int = 0) -> Optional[str]:
+        p = self.lib.self_player_get_tree_export(self.p, thread_id, format)
+        if not p:
+            return None
+        s = ctypes.string_at(p).decode('utf-8')
+        self.lib.destroy_c_string(p)
+        return s
+
+    # BATCH_SIZE
+    def get_value_targets_for_training(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_value_targets_for_training(self.p)).to_numpy()
+
+    # playerから見た最終的な石差をマスの数で割ったもの(-1..1)
+    def get_scores_for_training(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_scores_for_training(self.p)).to_numpy()
+
+    # [投了した試合数, 投了禁止の試合で投了するはずだった試合数, そのうち実際は勝った試合数, 誤投了率]
+    def get_resign_stats(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_resign_stats(self.p)).to_numpy()
+
     def get_results_for_counting(self) -> NDArray[float32]:
         return CArray(self.lib, self.lib.self_player_get_results_for_counting(self.p)).to_numpy()
 
-    def receive_prediction(self, pis: NDArray[float32], win_rates: NDArray[float32], player: int):
+    # 直前のget_boards_for_predictionの行の順に渡す。scoresはスコアヘッドがなければNone
+    def receive_prediction(self, pis: NDArray[float32], win_rates: NDArray[float32], player: int, scores: Optional[NDArray[float32]] = None):
         c_pis = CArray.from_numpy(self.lib, pis)
         c_win_rates = CArray.from_numpy(self.lib, win_rates)
-        self.lib.self_player_receive_prediction(
-            self.p, c_pis.p, c_win_rates.p, player)
+        c_scores = None if scores is None else CArray.from_numpy(self.lib, scores)
+        if not self.lib.self_player_receive_prediction(
+                self.p, c_pis.p, c_win_rates.p, None if c_scores is None else c_scores.p, player):
+            raise ValueError("predictions must have one row for each board of get_boards_for_prediction")
+
+    # これから終わる試合の教師データをpathのファイルにchunk_size個ずつ書き足す。開けなければFalse
+    def write_examples(self, path: str, chunk_size: int = 1024) -> bool:
+        return self.lib.self_player_write_examples(self.p, path.encode('utf-8'), chunk_size)
+
+    # 教師データの書き込みに失敗していればその理由。失敗するとそれ以降は書かない
+    def get_example_write_error(self) -> Optional[str]:
+        p = self.lib.self_player_get_example_write_error(self.p)
+        if not p:
+            return None
+        s = ctypes.string_at(p).decode('utf-8')
+        self.lib.destroy_c_string(p)
+        return s
+
+    # リプレイバッファからnum個(溜まっていなければ全部)取り出す。自己対戦の途中でも呼べる
+    def sample_replay(self, num: int) -> list[TrainExample]:
+        if self.lib.self_player_sample_replay(self.p, num) == 0:
+            return []
+        pis = CArray(self.lib, self.lib.self_player_get_replay_pis(self.p)).to_numpy()
+        boards = CArray(self.lib, self.lib.self_player_get_replay_boards(self.p)).to_numpy()
+        players = CArray(self.lib, self.lib.self_player_get_replay_players(self.p)).to_numpy()
+        value_targets = CArray(self.lib, self.lib.self_player_get_replay_value_targets(self.p)).to_numpy()
+        scores = CArray(self.lib, self.lib.self_player_get_replay_scores(self.p)).to_numpy()
+        return [TrainExample(board, player, pi, v, score) for pi, board, player, v, score in zip(pis, boards, players, value_targets, scores)]
+
+    # [溜まっている教師データの数, これまでに入れた試合の数, これまでに入れた教師データの数]
+    def get_replay_stats(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_replay_stats(self.p)).to_numpy()
+
+    # 同じ局面の教師データをまとめ、pi、value_target、scoreを平均する。まとめた後の数を返す
+    # 以後のget_*_for_trainingはまとめたものを返す。symmetryがTrueなら対称な盤面もまとめる
+    def dedup_train_examples(self, symmetry: bool = False) -> int:
+        return self.lib.self_player_dedup_train_examples(self.p, symmetry)
+
+    # 学習のサンプルの重み。まとめた数
+    def get_weights_for_training(self) -> NDArray[float32]:
+        return CArray(self.lib, self.lib.self_player_get_weights_for_training(self.p)).to_numpy()
 
     def get_train_examples(self) -> list[TrainExample]:
         pis = self.get_pis_for_training()
         boards = self.get_boards_for_training()
         players = self.get_players_for_training()
-        results = self.get_results_for_training()
-        return [TrainExample(board, player, pi, result) for pi, board, player, result in zip(pis, boards, players, results)]
+        value_targets = self.get_value_targets_for_training()
+        scores = self.get_scores_for_training()
+        weights = self.get_weights_for_training()
+        return [TrainExample(board, player, pi, v, score, w) for pi, board, player, v, score, w in zip(pis, boards, players, value_targets, scores, weights)]
 
 
 def define_self_player_funcs(lib: CDLL):
     lib.create_self_player.argtypes = [POINTER(c_void_p), c_size_t]
     lib.create_self_player.restype = POINTER(c_void_p)
+    lib.create_continuous_self_player.argtypes = [
+        POINTER(c_void_p), c_size_t, c_size_t]
+    lib.create_continuous_self_player.restype = POINTER(c_void_p)
+    lib.create_self_player_vs_uct.argtypes = [
+        POINTER(c_void_p), c_size_t, c_size_t, c_size_t]
+    lib.create_self_player_vs_uct.restype = POINTER(c_void_p)
+    lib.create_shared_search.argtypes = [
+        POINTER(c_void_p), POINTER(c_void_p), c_size_t, c_size_t]
+    lib.create_shared_search.restype = POINTER(c_void_p)
+    lib.create_self_player_vs_alpha_beta.argtypes = [
+        POINTER(c_void_p), c_size_t, c_size_t, c_uint64]
+    lib.create_self_player_vs_alpha_beta.restype = POINTER(c_void_p)
     lib.destroy_self_player.argtypes = [POINTER(c_void_p)]
+    lib.self_player_cancel.argtypes = [POINTER(c_void_p)]
 
     lib.self_player_prepare_next.argtypes = [
         POINTER(c_void_p), c_size_t]
@@ -80,6 +197,22 @@ def define_self_player_funcs(lib: CDLL):
         POINTER(c_void_p), c_size_t]
     lib.self_player_get_boards_for_prediction.restype = POINTER(
         c_void_p)
+    lib.self_player_get_batch_thread_ids.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_batch_thread_ids.restype = POINTER(
+        c_void_p)
+    lib.self_player_clear_eval_cache.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_set_network_version.argtypes = [
+        POINTER(c_void_p), c_uint64]
+    lib.self_player_stats.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_stats.restype = POINTER(
+        c_void_p)
+    lib.self_player_get_eval_cache_stats.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_eval_cache_stats.restype = POINTER(
+        c_void_p)
     lib.self_player_get_pis_for_training.argtypes = [
         POINTER(c_void_p)]
     lib.self_player_get_pis_for_training.restype = POINTER(
@@ -96,9 +229,60 @@ def define_self_player_funcs(lib: CDLL):
         POINTER(c_void_p)]
     lib.self_player_get_results_for_training.restype = POINTER(
         c_void_p)
+    lib.self_player_get_scores_for_training.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_scores_for_training.restype = POINTER(
+        c_void_p)
+    lib.self_player_get_value_targets_for_training.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_value_targets_for_training.restype = POINTER(
+        c_void_p)
+    lib.self_player_get_analysis_moves.argtypes = [
+        POINTER(c_void_p), c_size_t]
+    lib.self_player_get_analysis_moves.restype = POINTER(
+        c_void_p)
+    lib.self_player_get_analysis_pv.argtypes = [
+        POINTER(c_void_p), c_size_t]
+    lib.self_player_get_analysis_pv.restype = POINTER(
+        c_void_p)
+    lib.self_player_get_analysis_root_value.argtypes = [
+        POINTER(c_void_p), c_size_t]
+    lib.self_player_get_analysis_root_value.restype = c_float
+    lib.self_player_request_tree_export.argtypes = [
+        POINTER(c_void_p), c_size_t, c_size_t, c_size_t]
+    lib.self_player_request_tree_export.restype = c_bool
+    lib.self_player_get_tree_export.argtypes = [
+        POINTER(c_void_p), c_size_t, c_size_t]
+    lib.self_player_get_tree_export.restype = c_void_p
+    lib.destroy_c_string.argtypes = [c_void_p]
+    lib.self_player_dedup_train_examples.argtypes = [
+        POINTER(c_void_p), c_bool]
+    lib.self_player_dedup_train_examples.restype = c_size_t
+    lib.self_player_get_weights_for_training.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_weights_for_training.restype = POINTER(
+        c_void_p)
+    lib.self_player_get_resign_stats.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_resign_stats.restype = POINTER(
+        c_void_p)
     lib.self_player_get_results_for_counting.argtypes = [
         POINTER(c_void_p)]
     lib.self_player_get_results_for_counting.restype = POINTER(
         c_void_p)
+    lib.self_player_write_examples.argtypes = [
+        POINTER(c_void_p), c_char_p, c_size_t]
+    lib.self_player_write_examples.restype = c_bool
+    lib.self_player_get_example_write_error.argtypes = [
+        POINTER(c_void_p)]
+    lib.self_player_get_example_write_error.restype = c_void_p
+    lib.self_player_sample_replay.argtypes = [
+        POINTER(c_void_p), c_size_t]
+    lib.self_player_sample_replay.restype = c_size_t
+    for name in ['pis', 'boards', 'players', 'value_targets', 'scores', 'stats']:
+        f = getattr(lib, f'self_player_get_replay_{name}')
+        f.argtypes = [POINTER(c_void_p)]
+        f.restype = POINTER(c_void_p)
     lib.self_player_receive_prediction.argtypes = [
-        POINTER(c_void_p), POINTER(c_void_p), POINTER(c_void_p), c_size_t]
+        POINTER(c_void_p), POINTER(c_void_p), POINTER(c_void_p), POINTER(c_void_p), c_size_t]
+    lib.self_player_receive_prediction.restype = c_bool
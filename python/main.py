@@ -38,7 +38,7 @@ def main():
     #     log.info("Loading 'train_examples' from file...")
     #     c.load_train_examples()
 
-    pc = PyCommunicator(args.is_release)
+    pc = PyCommunicator(args.is_release, args.engine_config)
     c = Coach(pc, args)
 
     log.info('Starting the learning process')
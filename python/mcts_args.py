@@ -1,4 +1,5 @@
-from dataclasses import dataclass
+from dataclasses import dataclass, field
+from typing import Union
 
 
 @dataclass
@@ -30,3 +31,11 @@ class MctsArgs:
     epochs: int = 1
     cuda: bool = True
     num_channels: int = 512
+    # 石差を予測するスコアヘッドを持つ。古いチェックポイントとは互換性がない
+    score_head: bool = False
+    score_loss_weight: float = 0.5
+
+    # Rust側の探索と自己対戦の設定(EngineConfig)。
+    # 例: {"num_threads": 8, "mcts": {"num_mcts_sims": 100, "cpuct": 1.5, "temp_threshold": 10}}
+    # 文字列ならJSONかTOMLの設定ファイルのパス。空のdictならすべてデフォルト
+    engine_config: Union[dict, str] = field(default_factory=dict)
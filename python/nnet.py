@@ -1,5 +1,6 @@
 
 import os
+from typing import Optional
 import random
 
 import numpy as np
@@ -44,6 +45,8 @@ class NNetWrapper:
     def __init__(self, pc: PyCommunicator, args: MctsArgs):
         self.args = args
         self.nnet = onnet(pc, self.args)
+        # 重みを変えるたびに増やす。SelfPlayerの予測結果のキャッシュを捨てるのに使う
+        self.version = 0
         self.board_x = pc.size_x()
         self.board_y = pc.size_y()
 
@@ -58,6 +61,7 @@ class NNetWrapper:
         # いつもpylanceはこれに文句言うけど言われた通り直すとエラーになる
         optimizer = optim.Adam(self.nnet.parameters(),  # type: ignore
                                lr=self.args.lr)
+        self.version += 1
 
         for epoch in range(args.epochs):
             print("EPOCH ::: " + str(epoch + 1))
@@ -73,11 +77,11 @@ class NNetWrapper:
                 if args.random_choice:
                     sample_ids = np.random.randint(  # type: ignore
                         len(examples), size=self.batch_size)
-                    boards, target_pis, target_vs = map(
+                    boards, target_pis, target_vs, target_scores = map(
                         list,
                         zip(
                             *(
-                                ((examples[id].canonical_board, examples[id].pi, examples[id].v)  # type: ignore
+                                ((examples[id].canonical_board, examples[id].pi, examples[id].v, examples[id].score)  # type: ignore
                                  for id in sample_ids)
                             )
                         ),
@@ -86,11 +90,11 @@ class NNetWrapper:
                     # 相当意味のわかりにくいコード。アンパックで引数をn個にしてzipにぶちこむスクリプト言語特有のやりかた
                     # zipが可変引数だからn個ぶち込んでしまえる
                     # 3個のlistを返すiterableになるので、分解できる（これもスクリプト言語特有のやり方)
-                    boards, target_pis, target_vs = map(
+                    boards, target_pis, target_vs, target_scores = map(
                         list,
                         zip(
                             *(
-                                (example.canonical_board, example.pi, example.v)
+                                (example.canonical_board, example.pi, example.v, example.score)
                                 for example in examples[i*self.batch_size:(i+1)*self.batch_size]
                             )
                         ),
@@ -99,6 +103,7 @@ class NNetWrapper:
                 boards = np.array(boards).astype(np.float32)
                 target_pis = np.array(target_pis).astype(np.float32)
                 target_vs = np.array(target_vs).astype(np.float32)
+                target_scores = np.array(target_scores).astype(np.float32)
 
                 # なんでfloat64なのかさっぱりわからない。遅いでしょ
                 # boards = torch.Tensor(np.array(boards).astype(np.float64))
@@ -109,13 +114,19 @@ class NNetWrapper:
                 # target_vs = torch.Tensor(np.array(vs).astype(np.float64))
                 target_vs = torch.from_numpy(  # type: ignore
                     target_vs).contiguous().cuda()
+                target_scores = torch.from_numpy(  # type: ignore
+                    target_scores).contiguous().cuda()
 
-                out_pi, out_v = self.nnet(boards)
+                out_pi, out_v, out_score = self.nnet(boards)
 
                 l_pi = self.loss_pi(target_pis, out_pi)
                 l_v = self.loss_v(target_vs, out_v)
 
                 total_loss = l_pi + l_v
+                if out_score is not None:
+                    # 勝つだけでなく、大きく勝つことも学習させる
+                    total_loss = total_loss + args.score_loss_weight * \
+                        self.loss_v(target_scores, out_score)
 
                 pi_losses.update(l_pi.item(), boards.size(0))
                 v_losses.update(l_v.item(), boards.size(0))
@@ -126,26 +137,28 @@ class NNetWrapper:
                 total_loss.backward()  # type: ignore
                 optimizer.step()  # type: ignore
 
-    def predict(self, board: NDArray[float32]) -> tuple[NDArray[float32], NDArray[float32]]:
+    # スコアヘッドがなければscoreはNone
+    def predict(self, board: NDArray[float32]) -> tuple[NDArray[float32], NDArray[float32], Optional[NDArray[float32]]]:
         # start = time.time()
 
         board: Tensor = torch.Tensor(board)
         if self.args.cuda:
             board = board.contiguous().cuda()
 
-        board = board.view(self.batch_size, self.board_x, self.board_y)
+        board = board.view(-1, self.board_x, self.board_y)
 
         self.nnet.eval()
         with torch.no_grad():
-            pi, v = self.nnet(board)
+            pi, v, score = self.nnet(board)
 
         assert isinstance(pi, Tensor)
         assert isinstance(v, Tensor)
 
         r1 = torch.exp(pi).data.cpu().numpy()  # type: ignore
         r2 = v.data.cpu().numpy()  # type: ignore
+        r3 = None if score is None else score.data.cpu().numpy()  # type: ignore
 
-        return (r1, r2)  # type: ignore
+        return (r1, r2, r3)  # type: ignore
 
     def loss_pi(self, targets: Tensor, outputs: Tensor) -> Tensor:
         return -torch.sum(targets * outputs) / targets.size()[0]
@@ -184,3 +197,4 @@ class NNetWrapper:
         checkpoint = torch.load(  # type: ignore
             filepath, map_location=map_location, weights_only=True)
         self.nnet.load_state_dict(checkpoint["state_dict"])
+        self.version += 1
@@ -48,6 +48,8 @@ class OthelloNNet(nn.Module):
 
         self.fc3 = nn.Linear(512, self.action_size)
         self.fc4 = nn.Linear(512, 1)
+        if args.score_head:
+            self.fc5 = nn.Linear(512, 1)
 
     def forward(self, s: Tensor):
         s = s.view(-1, 1, self.board_x, self.board_y)
@@ -71,5 +73,6 @@ class OthelloNNet(nn.Module):
 
         pi = self.fc3(s)
         v = self.fc4(s)
+        score = torch.tanh(self.fc5(s)) if self.args.score_head else None
 
-        return F.log_softmax(pi, dim=1), torch.tanh(v)
+        return F.log_softmax(pi, dim=1), torch.tanh(v), score
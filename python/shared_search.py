@@ -0,0 +1,23 @@
+from typing import Optional
+
+from numpy.typing import NDArray
+from numpy import float32
+
+from .intf_py_communicator import PyCommunicator
+from .nnet import NNetWrapper
+
+
+# 一つの局面を全スレッドで共有する木で探索する。対局や局面の解析用
+# 戻り値はSelfPlayer.get_analysisと同じ (moves, pv, root_value)
+def search_position(pc: PyCommunicator, nnet: NNetWrapper, board: NDArray[float32], player: int, num_sims: int) -> Optional[tuple[NDArray[float32], NDArray[float32], float]]:
+    sp = pc.create_shared_search(board, player, num_sims)
+    while True:
+        rnum = sp.prepare_next(0)
+        if rnum == 0:
+            continue
+        elif rnum == 1:
+            pis, win_rates, scores = nnet.predict(
+                sp.get_boards_for_prediction(0))
+            sp.receive_prediction(pis, win_rates, 0, scores)
+        elif rnum == 2:
+            return sp.get_analysis(0)
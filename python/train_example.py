@@ -10,11 +10,15 @@ class TrainExample:
     canonical_board: NDArray[float32]
     cur_player: int
     pi: NDArray[float32]
-    v: int
+    v: float
+    # 最終的な石差をマスの数で割ったもの
+    score: float = 0.0
+    # 学習のサンプルの重み。同じ局面をまとめた数
+    weight: float = 1.0
 
     def to_str(self, title: str) -> str:
         return '\n'.join([title, board_to_str(self.canonical_board * self.cur_player),
-                          f"player {self.cur_player} result {self.v} diff {board_to_diff(self.canonical_board * self.cur_player)}",
+                          f"player {self.cur_player} result {self.v} score {self.score} diff {board_to_diff(self.canonical_board * self.cur_player)}",
                           pi_to_str(self.pi)])
 
 
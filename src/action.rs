@@ -23,9 +23,18 @@ impl Action {
         let a = self.0;
         return Move::new(a / N, a % N);
     }
+
+    /// オセロの棋譜の表記。列をa..、行を1..で表す(例: c4)。パスは"pass"
+    pub fn to_notation(self) -> String {
+        if self.is_pass() {
+            return "pass".to_string();
+        }
+        let m = self.to_move();
+        format!("{}{}", (b'a' + m.y() as u8) as char, m.x() + 1)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Pi {
     pub action_probs: Box<[f32; MOVE_LEN]>,
 }
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::action::{Action, Pi};
+use crate::analysis::Analysis;
+use crate::constant::{MOVE_LEN, N};
+use crate::othello_board::OthelloBoard;
+use crate::othello_game::{get_game_ended, get_next_state, get_valid_moves};
+use crate::player::Player;
+
+/// これを超えたら置換表を作り直す
+const TT_MAX_LEN: usize = 1 << 20;
+/// 何ノードごとに時間切れと中止を調べるか
+const TIME_CHECK_INTERVAL: usize = 256;
+
+/// 盤面の位置ごとの重み。隅が良く、隅に隣接するマスが悪い
+pub fn square_weight(x: usize, y: usize) -> f32 {
+    let edge = |i: usize| i == 0 || i == N - 1;
+    let next_to_edge = |i: usize| i == 1 || i == N - 2;
+    match (edge(x), edge(y), next_to_edge(x), next_to_edge(y)) {
+        (true, true, _, _) => 10.0,
+        (true, _, _, true) | (_, true, true, _) => -3.0,
+        (_, _, true, true) => -5.0,
+        (true, _, _, _) | (_, true, _, _) => 2.0,
+        _ => 0.0,
+    }
+}
+
+/// 空きマスに隣接している石の数
+fn frontier_discs(board: &OthelloBoard, color: i32) -> i32 {
+    let mut count = 0;
+    for x in 0..N {
+        for y in 0..N {
+            if board[x][y] != color {
+                continue;
+            }
+            let is_frontier = (-1i32..=1).any(|dx| {
+                (-1i32..=1).any(|dy| {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    0 <= nx
+                        && nx < N as i32
+                        && 0 <= ny
+                        && ny < N as i32
+                        && board[nx as usize][ny as usize] == 0
+                })
+            });
+            if is_frontier {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// 隅から辺に沿って同じ色が連続している石の数。これらの石は二度と返されない
+fn corner_stable_discs(board: &OthelloBoard, color: i32) -> i32 {
+    let last = N - 1;
+    let mut stable = [[false; N]; N];
+    for (cx, cy, dx, dy) in [
+        (0, 0, 1i32, 1i32),
+        (0, last, 1, -1),
+        (last, 0, -1, 1),
+        (last, last, -1, -1),
+    ] {
+        if board[cx][cy] != color {
+            continue;
+        }
+        for (sx, sy) in [(dx, 0), (0, dy)] {
+            let (mut x, mut y) = (cx as i32, cy as i32);
+            while 0 <= x && x < N as i32 && 0 <= y && y < N as i32 {
+                if board[x as usize][y as usize] != color {
+                    break;
+                }
+                stable[x as usize][y as usize] = true;
+                x += sx;
+                y += sy;
+            }
+        }
+    }
+    stable.iter().flatten().filter(|&&s| s).count() as i32
+}
+
+/// playerから見た盤面の評価値(-1..1)。
+/// 位置の重み、着手可能数、開放度(フロンティアの石)、隅からの確定石を合わせる
+pub fn heuristic_value(board: &OthelloBoard, player: Player) -> f32 {
+    let me = player.color();
+    let mut positional = 0.0;
+    for x in 0..N {
+        for y in 0..N {
+            positional += square_weight(x, y) * (board[x][y] * me) as f32;
+        }
+    }
+    let my_moves = board.get_legal_moves(player).len() as f32;
+    let op_moves = board.get_legal_moves(player.other()).len() as f32;
+    let mobility = if my_moves + op_moves == 0.0 {
+        0.0
+    } else {
+        10.0 * (my_moves - op_moves) / (my_moves + op_moves)
+    };
+    let frontier = (frontier_discs(board, -me) - frontier_discs(board, me)) as f32;
+    let stable = 3.0 * (corner_stable_discs(board, me) - corner_stable_discs(board, -me)) as f32;
+    ((positional + mobility + frontier + stable) / 20.0).tanh()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bound {
+    Exact,
+    /// 真の値はこれ以上
+    Lower,
+    /// 真の値はこれ以下
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TtEntry {
+    depth: usize,
+    value: f32,
+    bound: Bound,
+    best: usize,
+}
+
+/// 反復深化と置換表を使うアルファベータ探索。depthとtimeの少なくとも片方を指定する
+pub struct AlphaBeta {
+    depth: Option<usize>,
+    time: Option<Duration>,
+    tt: HashMap<u128, TtEntry>,
+    deadline: Option<Instant>,
+    nodes: usize,
+    aborted: bool,
+    /// trueになったら時間切れと同じように探索を打ち切る
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+/// 探索結果。valueは手番側から見た評価値
+#[derive(Debug, Clone)]
+pub struct AlphaBetaResult {
+    pub action: Action,
+    pub value: f32,
+    pub pv: Vec<Action>,
+}
+
+impl AlphaBeta {
+    pub fn new(depth: Option<usize>, time: Option<Duration>) -> Self {
+        if depth.is_none() && time.is_none() {
+            panic!("AlphaBeta needs depth or time");
+        }
+        Self {
+            depth,
+            time,
+            tt: HashMap::new(),
+            deadline: None,
+            nodes: 0,
+            aborted: false,
+            cancel: None,
+        }
+    }
+
+    pub fn set_cancel(&mut self, cancel: Arc<AtomicBool>) {
+        self.cancel = Some(cancel);
+    }
+
+    pub fn search(&mut self, board: &OthelloBoard, player: Player) -> AlphaBetaResult {
+        if TT_MAX_LEN < self.tt.len() {
+            self.tt.clear();
+        }
+        self.deadline = self.time.map(|t| Instant::now() + t);
+        self.aborted = false;
+        //depthの指定がなければ、時間切れか読み切るまで深くする
+        let max_depth = self.depth.unwrap_or(MOVE_LEN);
+
+        let mut result = None;
+        for depth in 1..=max_depth {
+            let value = self.negamax(board, player, depth, -f32::INFINITY, f32::INFINITY);
+            if self.aborted {
+                break;
+            }
+            let pv = self.principal_variation(board, player, depth);
+            result = Some(AlphaBetaResult {
+                action: pv[0],
+                value,
+                pv,
+            });
+            //勝敗が読み切れた
+            if value.abs() >= 1.0 {
+                break;
+            }
+        }
+        //一手目の途中で時間切れになった場合は、最も良さそうな手を返す
+        result.unwrap_or_else(|| {
+            let action = self.ordered_actions(board, player, None)[0];
+            AlphaBetaResult {
+                action,
+                value: heuristic_value(board, player),
+                pv: vec![action],
+            }
+        })
+    }
+
+    /// MCTSと同じ形で返す。Piは選んだ手だけ1
+    pub fn decide_move(&mut self, board: &OthelloBoard, player: Player) -> (Action, Pi, Analysis) {
+        let result = self.search(board, player);
+        let mut probs = [0.0; MOVE_LEN];
+        probs[result.action._val()] = 1.0;
+        let analysis = Analysis {
+            moves: vec![],
+            pv: result.pv,
+            root_value: result.value,
+        };
+        (result.action, Pi::new(&probs), analysis)
+    }
+
+    fn key(board: &OthelloBoard, player: Player) -> u128 {
+        board.create_canonical_board(player).string_representation()
+    }
+
+    /// 置換表の最善手を先に、残りは位置の重みの大きい順に並べる
+    fn ordered_actions(
+        &self,
+        board: &OthelloBoard,
+        player: Player,
+        tt_best: Option<usize>,
+    ) -> Vec<Action> {
+        let valids = get_valid_moves(board, player);
+        let mut actions: Vec<usize> = (0..MOVE_LEN).filter(|&a| valids[a]).collect();
+        actions.sort_by(|&a, &b| {
+            let weight = |a: usize| {
+                if Some(a) == tt_best {
+                    f32::INFINITY
+                } else if Action::new(a).is_pass() {
+                    0.0
+                } else {
+                    square_weight(a / N, a % N)
+                }
+            };
+            weight(b).partial_cmp(&weight(a)).unwrap()
+        });
+        actions.into_iter().map(Action::new).collect()
+    }
+
+    fn negamax(
+        &mut self,
+        board: &OthelloBoard,
+        player: Player,
+        depth: usize,
+        mut alpha: f32,
+        beta: f32,
+    ) -> f32 {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(TIME_CHECK_INTERVAL) {
+            let timed_out = self.deadline.is_some_and(|deadline| deadline <= Instant::now());
+            let cancelled = self
+                .cancel
+                .as_ref()
+                .is_some_and(|cancel| cancel.load(Ordering::Relaxed));
+            if timed_out || cancelled {
+                self.aborted = true;
+            }
+        }
+        if self.aborted {
+            return 0.0;
+        }
+
+        let r = get_game_ended(board, player);
+        if r != 0 {
+            return r as f32;
+        }
+        if depth == 0 {
+            return heuristic_value(board, player);
+        }
+
+        let key = Self::key(board, player);
+        let entry = self.tt.get(&key).copied();
+        if let Some(e) = entry {
+            if depth <= e.depth {
+                match e.bound {
+                    Bound::Exact => return e.value,
+                    Bound::Lower if beta <= e.value => return e.value,
+                    Bound::Upper if e.value <= alpha => return e.value,
+                    _ => {}
+                }
+            }
+        }
+
+        let alpha_orig = alpha;
+        let mut best_value = -f32::INFINITY;
+        let mut best = 0;
+        for action in self.ordered_actions(board, player, entry.map(|e| e.best)) {
+            let mut next = board.clone();
+            get_next_state(&mut next, player, action);
+            let v = -self.negamax(&next, player.other(), depth - 1, -beta, -alpha);
+            if self.aborted {
+                return 0.0;
+            }
+            if best_value < v {
+                best_value = v;
+                best = action._val();
+            }
+            alpha = alpha.max(v);
+            if beta <= alpha {
+                break;
+            }
+        }
+
+        let bound = if best_value <= alpha_orig {
+            Bound::Upper
+        } else if beta <= best_value {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.tt.insert(
+            key,
+            TtEntry {
+                depth,
+                value: best_value,
+                bound,
+                best,
+            },
+        );
+        best_value
+    }
+
+    /// 置換表の最善手を辿った読み筋
+    fn principal_variation(
+        &self,
+        board: &OthelloBoard,
+        player: Player,
+        depth: usize,
+    ) -> Vec<Action> {
+        let mut board = board.clone();
+        let mut player = player;
+        let mut pv = vec![];
+        while pv.len() < depth && get_game_ended(&board, player) == 0 {
+            let action = match self.tt.get(&Self::key(&board, player)) {
+                Some(e) => Action::new(e.best),
+                None => break,
+            };
+            pv.push(action);
+            get_next_state(&mut board, player, action);
+            player = player.other();
+        }
+        pv
+    }
+}
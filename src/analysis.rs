@@ -0,0 +1,115 @@
+use std::sync::{Arc, Mutex};
+
+use crate::action::{Action, Pi};
+use crate::constant::MOVE_LEN;
+use crate::evaluator::Evaluator;
+use crate::mcts::{puct_score, BoardState, Mcts, Turn};
+use crate::othello_board::OthelloBoard;
+use crate::othello_game::get_next_state;
+use crate::player::Player;
+use crate::search_limit::SearchLimit;
+use crate::thread_id::ThreadID;
+
+/// ルートの合法手一つ分の探索結果。qとucbはルートの手番側から見たもの
+#[derive(Debug, Clone)]
+pub struct MoveAnalysis {
+    pub action: Action,
+    pub visits: usize,
+    /// 未訪問なら0
+    pub q: f32,
+    pub prior: f32,
+    pub ucb: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub moves: Vec<MoveAnalysis>,
+    /// 最も訪問された子を辿った読み筋
+    pub pv: Vec<Action>,
+    /// ルートの手番側から見た探索Q
+    pub root_value: f32,
+}
+
+impl<'a, E: Evaluator + ?Sized> Mcts<'a, E> {
+    pub fn get_action_prob_with_analysis(
+        &mut self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        turn: Turn,
+        temp: f32,
+        limit: SearchLimit,
+    ) -> Option<(Pi, Analysis)> {
+        let pi = self.get_action_prob(unorthodox_board, player, turn, temp, limit)?;
+        Some((pi, self.analyze(unorthodox_board, player)))
+    }
+
+    /// ルートが展開されていなければmovesとpvは空
+    pub fn analyze(&self, unorthodox_board: &OthelloBoard, player: Player) -> Analysis {
+        let s = unorthodox_board
+            .create_canonical_board(player)
+            .string_representation();
+
+        let mut moves = vec![];
+        if let Some(node_info) = self.node.get(&s) {
+            for a in 0..MOVE_LEN {
+                if node_info.valid_moves[a] {
+                    let node_act = self.node_act.get(&BoardState::new(s, a));
+                    moves.push(MoveAnalysis {
+                        action: Action::new(a),
+                        visits: node_act.map_or(0, |info| info.count),
+                        q: node_act.map_or(0.0, |info| info.win_rate),
+                        prior: node_info.predicted_pi[a],
+                        ucb: puct_score(self.args.cpuct, node_info, a, node_act),
+                    });
+                }
+            }
+        }
+
+        Analysis {
+            moves,
+            pv: self.principal_variation(unorthodox_board, player),
+            root_value: self.root_q(unorthodox_board, player),
+        }
+    }
+
+    fn principal_variation(&self, unorthodox_board: &OthelloBoard, player: Player) -> Vec<Action> {
+        let mut board = unorthodox_board.clone();
+        let mut player = player;
+        let mut pv = vec![];
+        loop {
+            let s = board.create_canonical_board(player).string_representation();
+            let best = (0..MOVE_LEN)
+                .filter_map(|a| {
+                    self.node_act
+                        .get(&BoardState::new(s, a))
+                        .map(|info| (a, info.count))
+                })
+                .filter(|&(_, count)| count != 0)
+                .max_by_key(|&(_, count)| count);
+            let Some((a, _)) = best else {
+                return pv;
+            };
+            pv.push(Action::new(a));
+            get_next_state(&mut board, player, Action::new(a));
+            player = player.other();
+        }
+    }
+}
+
+/// 各スレッドが最後に行ったルート探索の解析結果。SelfPlayerからFFI経由で参照する
+#[derive(Clone)]
+pub struct AnalysisSlots(Arc<Vec<Mutex<Option<Analysis>>>>);
+
+impl AnalysisSlots {
+    pub fn new(len: usize) -> Self {
+        Self(Arc::new((0..len).map(|_| Mutex::new(None)).collect()))
+    }
+
+    pub fn set(&self, thread_id: &ThreadID, analysis: Analysis) {
+        *self.0[thread_id.id()].lock().unwrap() = Some(analysis);
+    }
+
+    pub fn get(&self, thread_id: usize) -> Option<Analysis> {
+        self.0.get(thread_id)?.lock().unwrap().clone()
+    }
+}
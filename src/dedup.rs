@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use crate::{
+    action::Pi,
+    constant::{BOARD_SIZE, MOVE_LEN, N},
+    example_batch::ExampleBatch,
+    mcts::TrainExample,
+    othello_board::OthelloBoard,
+};
+
+/// 盤面の8つの対称変換。kの4のビットで転置し、1のビットでxを、2のビットでyを反転する
+fn transform(k: usize, x: usize, y: usize) -> (usize, usize) {
+    let (x, y) = if k & 4 != 0 { (y, x) } else { (x, y) };
+    let x = if k & 1 != 0 { N - 1 - x } else { x };
+    let y = if k & 2 != 0 { N - 1 - y } else { y };
+    (x, y)
+}
+
+fn transform_board(k: usize, board: &OthelloBoard) -> OthelloBoard {
+    let mut r = OthelloBoard::new();
+    for x in 0..N {
+        for y in 0..N {
+            let (tx, ty) = transform(k, x, y);
+            r[tx][ty] = board[x][y];
+        }
+    }
+    r
+}
+
+/// パスはどの変換でも動かない
+fn transform_pi(k: usize, probs: &[f32]) -> [f32; MOVE_LEN] {
+    let mut r = [0.0; MOVE_LEN];
+    for x in 0..N {
+        for y in 0..N {
+            let (tx, ty) = transform(k, x, y);
+            r[tx * N + ty] = probs[x * N + y];
+        }
+    }
+    r[BOARD_SIZE] = probs[BOARD_SIZE];
+    r
+}
+
+/// 同じ局面の教師データ。piなどは合計しておき、最後に数で割る
+struct Entry {
+    first: TrainExample,
+    board: OthelloBoard,
+    pi_sum: [f32; MOVE_LEN],
+    result_sum: i32,
+    q_sum: f32,
+    value_target_sum: f32,
+    score_sum: f32,
+    count: usize,
+}
+
+/// 同じ局面の教師データを一つにまとめ、pi、value_target、scoreを平均する。
+/// まとめた数は学習のサンプルの重みとして使う。
+/// 局面はcanonical boardと手番で区別する。symmetryがtrueなら対称な盤面も同じ局面とし、
+/// ハッシュが最小になる向きにそろえる
+pub struct Deduplicator {
+    symmetry: bool,
+    index: HashMap<(u128, i32), usize>,
+    entries: Vec<Entry>,
+}
+
+impl Deduplicator {
+    pub fn new(symmetry: bool) -> Self {
+        Self {
+            symmetry,
+            index: HashMap::new(),
+            entries: vec![],
+        }
+    }
+
+    pub fn add(&mut self, example: &TrainExample) {
+        let (board, pi) = if self.symmetry {
+            let k = (0..8)
+                .min_by_key(|&k| transform_board(k, &example.canonical_board).string_representation())
+                .unwrap();
+            (
+                transform_board(k, &example.canonical_board),
+                transform_pi(k, example.pi.probs()),
+            )
+        } else {
+            (
+                example.canonical_board.clone(),
+                example.pi.probs().try_into().unwrap(),
+            )
+        };
+        let key = (board.string_representation(), example.player.color());
+        let index = *self.index.entry(key).or_insert_with(|| {
+            self.entries.push(Entry {
+                first: example.clone(),
+                board,
+                pi_sum: [0.0; MOVE_LEN],
+                result_sum: 0,
+                q_sum: 0.0,
+                value_target_sum: 0.0,
+                score_sum: 0.0,
+                count: 0,
+            });
+            self.entries.len() - 1
+        });
+        let entry = &mut self.entries[index];
+        for (sum, p) in entry.pi_sum.iter_mut().zip(pi) {
+            *sum += p;
+        }
+        entry.result_sum += example.result;
+        entry.q_sum += example._q;
+        entry.value_target_sum += example.value_target;
+        entry.score_sum += example.score;
+        entry.count += 1;
+    }
+
+    /// 最初に現れた順に返す。resultは平均を丸めたもの
+    pub fn finish(self) -> ExampleBatch {
+        let mut examples = Vec::with_capacity(self.entries.len());
+        let mut counts = Vec::with_capacity(self.entries.len());
+        for entry in self.entries {
+            let n = entry.count as f32;
+            let probs: Vec<f32> = entry.pi_sum.iter().map(|p| p / n).collect();
+            examples.push(TrainExample {
+                pi: Pi::new(&probs),
+                canonical_board: entry.board,
+                result: (entry.result_sum as f32 / n).round() as i32,
+                _q: entry.q_sum / n,
+                value_target: entry.value_target_sum / n,
+                score: entry.score_sum / n,
+                ..entry.first
+            });
+            counts.push(entry.count);
+        }
+        ExampleBatch { examples, counts }
+    }
+}
+
+pub fn dedup<'a, I>(examples: I, symmetry: bool) -> ExampleBatch
+where
+    I: IntoIterator<Item = &'a TrainExample>,
+{
+    let mut deduplicator = Deduplicator::new(symmetry);
+    for example in examples {
+        deduplicator.add(example);
+    }
+    deduplicator.finish()
+}
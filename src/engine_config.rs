@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::mcts_args::MctsArgs;
+use crate::self_player::SelfPlayConfig;
+
+/// PyCommunicatorの設定。JSONかTOMLで書く。知らないキーはエラーにする
+///
+/// ```toml
+/// num_threads = 8
+/// concurrent_games = 1024
+/// batch_size = 256
+/// games_per_generation = 2000
+/// min_batch_size = 192
+/// batch_timeout_us = 500
+///
+/// [mcts]
+/// num_mcts_sims = 100
+/// root_search = "gumbel"
+/// search_budget = { time_per_move = 0.5 }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// 探索と自己対戦の設定
+    pub mcts: MctsArgs,
+    /// 試合を進めるワーカースレッドの数。Noneなら論理CPUの数
+    pub num_threads: Option<usize>,
+    /// 同時に進める試合の数
+    pub concurrent_games: usize,
+    /// NNに一度に渡す盤面の最大数
+    pub batch_size: usize,
+    /// 一つのSelfPlayerで行う試合の数。試合が終わったスロットでは、これに達するまで次の試合を始める
+    pub games_per_generation: usize,
+    /// これだけの盤面が揃ったらバッチを返す
+    pub min_batch_size: usize,
+    /// 揃っていなくても、これだけ待ったら揃った分だけでバッチを返す(マイクロ秒)
+    pub batch_timeout_us: u64,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        let defaults = SelfPlayConfig::default();
+        Self {
+            mcts: MctsArgs::default(),
+            num_threads: None,
+            concurrent_games: defaults.concurrent_games,
+            batch_size: defaults.batch_size,
+            games_per_generation: defaults.games_per_generation,
+            min_batch_size: defaults.min_batch_size,
+            batch_timeout_us: defaults.batch_timeout.as_micros() as u64,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// '{'で始まればJSON、それ以外はTOMLとして読む
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let config: Self = if text.trim_start().starts_with('{') {
+            serde_json::from_str(text).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())?
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.mcts.validate()?;
+        if self.num_threads == Some(0) {
+            return Err("num_threads must be > 0".to_string());
+        }
+        self.self_play().validate()
+    }
+
+    pub fn self_play(&self) -> SelfPlayConfig {
+        SelfPlayConfig {
+            concurrent_games: self.concurrent_games,
+            batch_size: self.batch_size,
+            games_per_generation: self.games_per_generation,
+            min_batch_size: self.min_batch_size,
+            batch_timeout: Duration::from_micros(self.batch_timeout_us),
+        }
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.num_threads
+            .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::player::Player;
+use crate::predict_result::PredictResult;
+
+const NUM_SHARDS: usize = 16;
+
+/// SelfPlayerの全スレッドで共有するNNの予測結果のキャッシュ。canonical boardのハッシュで引く。
+/// 序盤の局面は試合間で何度も現れるので、Pythonに送らずに済む
+pub struct EvalCache {
+    shards: Vec<Mutex<CacheShard>>,
+    capacity_per_shard: usize,
+    /// 2Playerモードでは思考担当ごとにNNが違うので、思考担当もキーに含める
+    per_player: bool,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    /// 予測したNNの重みの版。set_network_versionで変わったら中身を捨てる
+    network_version: AtomicU64,
+}
+
+struct CacheShard {
+    map: HashMap<(u128, i32), PredictResult>,
+    /// 古いものから捨てる
+    order: VecDeque<(u128, i32)>,
+}
+
+impl EvalCache {
+    /// capacityが0ならキャッシュしない
+    pub fn new(capacity: usize, per_player: bool) -> Self {
+        Self {
+            shards: (0..NUM_SHARDS)
+                .map(|_| {
+                    Mutex::new(CacheShard {
+                        map: HashMap::new(),
+                        order: VecDeque::new(),
+                    })
+                })
+                .collect(),
+            capacity_per_shard: capacity.div_ceil(NUM_SHARDS),
+            per_player,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            network_version: AtomicU64::new(0),
+        }
+    }
+
+    fn key(&self, s: u128, thinking_player: Player) -> (u128, i32) {
+        let p = if self.per_player {
+            thinking_player.color()
+        } else {
+            0
+        };
+        (s, p)
+    }
+
+    fn shard(&self, s: u128) -> &Mutex<CacheShard> {
+        &self.shards[(s ^ (s >> 64)) as usize % NUM_SHARDS]
+    }
+
+    pub fn get(&self, s: u128, thinking_player: Player) -> Option<PredictResult> {
+        if self.capacity_per_shard == 0 {
+            return None;
+        }
+        let key = self.key(s, thinking_player);
+        let r = self.shard(s).lock().unwrap().map.get(&key).cloned();
+        if r.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        r
+    }
+
+    pub fn insert(&self, s: u128, thinking_player: Player, r: PredictResult) {
+        if self.capacity_per_shard == 0 {
+            return;
+        }
+        let key = self.key(s, thinking_player);
+        let mut shard = self.shard(s).lock().unwrap();
+        if shard.map.insert(key, r).is_none() {
+            shard.order.push_back(key);
+            if self.capacity_per_shard < shard.order.len() {
+                let old = shard.order.pop_front().unwrap();
+                shard.map.remove(&old);
+            }
+        }
+    }
+
+    /// NNの重みを入れ替えるたびに違うversionを渡す。前と違えばキャッシュを捨てる
+    pub fn set_network_version(&self, version: u64) {
+        if self.network_version.swap(version, Ordering::AcqRel) != version {
+            self.clear();
+        }
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            shard.map.clear();
+            shard.order.clear();
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
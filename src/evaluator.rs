@@ -0,0 +1,256 @@
+use std::sync::{mpsc, Arc, Mutex};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use crate::action::{Action, Pi};
+use crate::alpha_beta::{heuristic_value, square_weight};
+use crate::constant::{MOVE_LEN, N};
+use crate::eval_cache::EvalCache;
+use crate::mcts::{MainToThread, ThreadToMain};
+use crate::othello_board::OthelloBoard;
+use crate::othello_game::{get_game_ended, get_next_state, get_valid_moves};
+use crate::player::Player;
+use crate::predict_result::PredictResult;
+use crate::thread_id::ThreadID;
+
+/// 局面の評価器。MCTSはこれを通してのみ評価を得る
+pub trait Evaluator {
+    /// boardsはcanonical board(Player1の手番)。それぞれPlayer1から見た予測を返す
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult>;
+
+    /// 評価をすぐに返せなければNoneでよい。Noneを受けた探索は中断し、後で同じ盤面からやり直す
+    fn try_evaluate(&mut self, board: &OthelloBoard) -> Option<PredictResult> {
+        self.evaluate(std::slice::from_ref(board)).pop()
+    }
+}
+
+pub type BoxedEvaluator = Box<dyn Evaluator + Send>;
+
+/// メインスレッドに盤面を送り、Python側のNNの予測を待つ。
+/// SelfPlayerが全スレッドの盤面をまとめてバッチにする
+///
+/// 2Playerモードでは一つのスレッドの両プレイヤーが同じチャンネルを使うので、受信側は共有する
+pub struct ChannelEvaluator {
+    send_to_main: mpsc::Sender<ThreadToMain>,
+    receive_from_main: Arc<Mutex<mpsc::Receiver<MainToThread>>>,
+    thread_id: ThreadID,
+    thinking_player: Player,
+    eval_cache: Arc<EvalCache>,
+}
+
+impl ChannelEvaluator {
+    pub fn new(
+        send_to_main: mpsc::Sender<ThreadToMain>,
+        receive_from_main: Arc<Mutex<mpsc::Receiver<MainToThread>>>,
+        thread_id: ThreadID,
+        thinking_player: Player,
+        eval_cache: Arc<EvalCache>,
+    ) -> Self {
+        Self {
+            send_to_main,
+            receive_from_main,
+            thread_id,
+            thinking_player,
+            eval_cache,
+        }
+    }
+}
+
+impl Evaluator for ChannelEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards
+            .iter()
+            .map(|board| {
+                let s = board.string_representation();
+                if let Some(r) = self.eval_cache.get(s, self.thinking_player) {
+                    return r;
+                }
+                self.send_to_main
+                    .send(ThreadToMain::Board(
+                        board.clone(),
+                        self.thread_id.clone(),
+                        self.thinking_player,
+                    ))
+                    .unwrap();
+                let MainToThread::Prediction(r, _) =
+                    self.receive_from_main.lock().unwrap().recv().unwrap();
+                self.eval_cache.insert(s, self.thinking_player, r.clone());
+                r
+            })
+            .collect()
+    }
+}
+
+/// 評価を待つ盤面と、届いた評価。一つの試合の両プレイヤーのSuspendingEvaluatorで共有する
+#[derive(Default)]
+pub struct PendingEval {
+    request: Option<(OthelloBoard, Player)>,
+    response: Option<(u128, PredictResult)>,
+}
+
+impl PendingEval {
+    pub fn shared() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self::default()))
+    }
+
+    /// 評価を待っている盤面(canonical board)と思考担当
+    pub fn request(&self) -> Option<(OthelloBoard, Player)> {
+        self.request.clone()
+    }
+
+    /// 待っている盤面の評価を渡す
+    pub fn respond(&mut self, r: PredictResult) {
+        let (board, _) = self
+            .request
+            .take()
+            .expect("no board is waiting for evaluation");
+        self.response = Some((board.string_representation(), r));
+    }
+}
+
+/// 評価が届いていなければ、盤面をPendingEvalに置いてNoneを返す。
+/// 探索はそこで中断するので、スレッドを止めずに多数の試合を少ないスレッドで進められる
+pub struct SuspendingEvaluator {
+    pending: Arc<Mutex<PendingEval>>,
+    thinking_player: Player,
+    eval_cache: Arc<EvalCache>,
+}
+
+impl SuspendingEvaluator {
+    pub fn new(
+        pending: Arc<Mutex<PendingEval>>,
+        thinking_player: Player,
+        eval_cache: Arc<EvalCache>,
+    ) -> Self {
+        Self {
+            pending,
+            thinking_player,
+            eval_cache,
+        }
+    }
+}
+
+impl Evaluator for SuspendingEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards
+            .iter()
+            .map(|board| {
+                self.try_evaluate(board)
+                    .expect("SuspendingEvaluator cannot wait for evaluation")
+            })
+            .collect()
+    }
+
+    fn try_evaluate(&mut self, board: &OthelloBoard) -> Option<PredictResult> {
+        let s = board.string_representation();
+        let mut pending = self.pending.lock().unwrap();
+        //探索をやり直すと別の盤面を求めることもある(時間制限など)。その場合もキャッシュには入れておく
+        if let Some((key, r)) = pending.response.take() {
+            self.eval_cache.insert(key, self.thinking_player, r.clone());
+            if key == s {
+                return Some(r);
+            }
+        }
+        if let Some(r) = self.eval_cache.get(s, self.thinking_player) {
+            return Some(r);
+        }
+        pending.request = Some((board.clone(), self.thinking_player));
+        None
+    }
+}
+
+/// 一様な方策と勝率0を返す
+pub struct UniformEvaluator;
+
+impl Evaluator for UniformEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards
+            .iter()
+            .map(|_| PredictResult {
+                win_rate: 0.0,
+                action_probs: Pi::new(&[1.0 / MOVE_LEN as f32; MOVE_LEN]),
+                score: None,
+            })
+            .collect()
+    }
+}
+
+/// alpha_betaと同じ手作りの評価関数で評価する。方策はマスの位置の重みから作る。
+/// NNがまだない段階でMCTSを動かすのに使う
+pub struct HeuristicEvaluator;
+
+impl Evaluator for HeuristicEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards
+            .iter()
+            .map(|board| {
+                let mut probs = [0.0; MOVE_LEN];
+                for (a, p) in probs.iter_mut().enumerate() {
+                    *p = if Action::new(a).is_pass() {
+                        1.0
+                    } else {
+                        (square_weight(a / N, a % N) / 5.0).exp()
+                    };
+                }
+                PredictResult {
+                    //渡される盤面はcanonicalなので、手番は常にPLAYER1
+                    win_rate: heuristic_value(board, Player::PLAYER1),
+                    action_probs: Pi::new(&probs),
+                    score: None,
+                }
+            })
+            .collect()
+    }
+}
+
+/// 終局までランダムに打って勝率と石差を求める。方策は一様
+pub struct RolloutEvaluator {
+    num_rollouts: usize,
+    rng: StdRng,
+}
+
+impl RolloutEvaluator {
+    pub fn new(num_rollouts: usize, rng: StdRng) -> Self {
+        Self { num_rollouts, rng }
+    }
+
+    /// Player1から見た勝敗と石差
+    fn rollout(&mut self, board: &OthelloBoard) -> (i32, i32) {
+        let mut board = board.clone();
+        let mut player = Player::PLAYER1;
+        loop {
+            let r = get_game_ended(&board, player);
+            if r != 0 {
+                return (r * player.color(), board.count_diff(Player::PLAYER1));
+            }
+            let valids = get_valid_moves(&board, player);
+            let actions: Vec<usize> = (0..MOVE_LEN).filter(|&a| valids[a]).collect();
+            let a = *actions.choose(&mut self.rng).unwrap();
+            get_next_state(&mut board, player, Action::new(a));
+            player = player.other();
+        }
+    }
+}
+
+impl Evaluator for RolloutEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards
+            .iter()
+            .map(|board| {
+                let (mut sum, mut diff_sum) = (0, 0);
+                for _ in 0..self.num_rollouts {
+                    let (r, diff) = self.rollout(board);
+                    sum += r;
+                    diff_sum += diff;
+                }
+                let n = self.num_rollouts.max(1) as f32;
+                PredictResult {
+                    win_rate: sum as f32 / n,
+                    action_probs: Pi::new(&[1.0 / MOVE_LEN as f32; MOVE_LEN]),
+                    score: Some(diff_sum as f32 / n / (N * N) as f32),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{
+    c_array::CArray,
+    constant::{MOVE_LEN, N},
+    mcts::TrainExample,
+    self_player::copy_board,
+};
+
+/// 学習のミニバッチ。リプレイバッファやファイルから取り出したものを、Pythonに配列で渡す
+#[derive(Default)]
+pub struct ExampleBatch {
+    pub examples: Vec<TrainExample>,
+    /// 重複をまとめたときの、それぞれの教師データにまとめた数。空ならすべて1
+    pub counts: Vec<usize>,
+}
+
+impl ExampleBatch {
+    pub fn new(examples: Vec<TrainExample>) -> Self {
+        Self {
+            examples,
+            counts: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.examples.len()
+    }
+
+    pub fn get_pis(&self) -> CArray<f32> {
+        let mut array = CArray::<f32>::new2(self.len(), MOVE_LEN);
+        for (idx, example) in self.examples.iter().enumerate() {
+            array.ref_mut2(idx).copy_from_slice(example.pi.probs())
+        }
+        array
+    }
+
+    pub fn get_boards(&self) -> CArray<f32> {
+        let mut array = CArray::<f32>::new3(self.len(), N, N);
+        for (idx, example) in self.examples.iter().enumerate() {
+            copy_board(array.ref_mut3_1(idx), &example.canonical_board);
+        }
+        array
+    }
+
+    pub fn get_players(&self) -> CArray<f32> {
+        self.values(|e| e.player.color() as f32)
+    }
+
+    pub fn get_value_targets(&self) -> CArray<f32> {
+        self.values(|e| e.value_target)
+    }
+
+    pub fn get_scores(&self) -> CArray<f32> {
+        self.values(|e| e.score)
+    }
+
+    /// 学習のサンプルの重み。まとめた数
+    pub fn get_weights(&self) -> CArray<f32> {
+        let mut array = CArray::<f32>::new1(self.len());
+        for (idx, w) in array.as_mut().iter_mut().enumerate() {
+            *w = self.counts.get(idx).map_or(1.0, |&c| c as f32);
+        }
+        array
+    }
+
+    fn values<F: Fn(&TrainExample) -> f32>(&self, f: F) -> CArray<f32> {
+        let mut array = CArray::<f32>::new1(self.len());
+        for (idx, example) in self.examples.iter().enumerate() {
+            array.as_mut()[idx] = f(example);
+        }
+        array
+    }
+}
@@ -0,0 +1,427 @@
+use std::ffi::{c_char, CStr};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{
+    action::Pi,
+    c_array::CArray,
+    constant::{BOARD_SIZE, MOVE_LEN, N},
+    dedup::Deduplicator,
+    example_batch::ExampleBatch,
+    mcts::{TrainExample, Turn},
+    othello_board::OthelloBoard,
+    player::Player,
+    self_player::write_c_error,
+};
+
+/// 教師データのファイル形式
+///
+/// ヘッダ: MAGIC, FORMAT_VERSION(u32), N(u32), MOVE_LEN(u32)
+///
+/// その後にチャンクが続く。チャンクは教師データの数(u32)と、その数のレコード。
+/// 数値はすべてリトルエンディアン。最後のチャンクが書きかけなら読むときに無視する
+///
+/// レコード:
+/// - canonical boardの1のマスと-1のマスのビット列(それぞれPLANE_BYTES)
+/// - piを65535倍して丸めたもの(u16 × MOVE_LEN)
+/// - result(i8), player(i8), 手数(u16)
+/// - ルートの探索Q, value_target, score(f32)
+pub const MAGIC: [u8; 4] = *b"OTEX";
+pub const FORMAT_VERSION: u32 = 1;
+
+const HEADER_SIZE: usize = 16;
+const CHUNK_HEADER_SIZE: usize = 4;
+const PLANE_BYTES: usize = BOARD_SIZE.div_ceil(8);
+pub const RECORD_SIZE: usize = PLANE_BYTES * 2 + MOVE_LEN * 2 + 4 + 4 * 3;
+const PI_SCALE: f32 = u16::MAX as f32;
+
+fn header() -> [u8; HEADER_SIZE] {
+    let mut h = [0; HEADER_SIZE];
+    h[0..4].copy_from_slice(&MAGIC);
+    h[4..8].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    h[8..12].copy_from_slice(&(N as u32).to_le_bytes());
+    h[12..16].copy_from_slice(&(MOVE_LEN as u32).to_le_bytes());
+    h
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn check_header(h: &[u8; HEADER_SIZE]) -> io::Result<()> {
+    let field = |i: usize| u32::from_le_bytes(h[i..i + 4].try_into().unwrap());
+    if h[0..4] != MAGIC {
+        return Err(invalid_data("not a train example file".to_string()));
+    }
+    if field(4) != FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "format version {} is not supported",
+            field(4)
+        )));
+    }
+    if field(8) != N as u32 || field(12) != MOVE_LEN as u32 {
+        return Err(invalid_data(format!(
+            "board size {} does not match {N}",
+            field(8)
+        )));
+    }
+    Ok(())
+}
+
+fn encode(example: &TrainExample, out: &mut Vec<u8>) {
+    let mut planes = [0u8; PLANE_BYTES * 2];
+    for (i, &c) in example.canonical_board.0.as_flattened().iter().enumerate() {
+        match c {
+            1 => planes[i / 8] |= 1 << (i % 8),
+            -1 => planes[PLANE_BYTES + i / 8] |= 1 << (i % 8),
+            _ => {}
+        }
+    }
+    out.extend_from_slice(&planes);
+    for &p in example.pi.probs() {
+        let q = (p.clamp(0.0, 1.0) * PI_SCALE).round() as u16;
+        out.extend_from_slice(&q.to_le_bytes());
+    }
+    out.push(example.result as i8 as u8);
+    out.push(example.player.color() as i8 as u8);
+    out.extend_from_slice(&(example._turn.0 as u16).to_le_bytes());
+    out.extend_from_slice(&example._q.to_le_bytes());
+    out.extend_from_slice(&example.value_target.to_le_bytes());
+    out.extend_from_slice(&example.score.to_le_bytes());
+}
+
+fn decode(record: &[u8]) -> TrainExample {
+    let mut board = OthelloBoard::new();
+    for (i, c) in board.0.as_flattened_mut().iter_mut().enumerate() {
+        let bit = 1 << (i % 8);
+        if record[i / 8] & bit != 0 {
+            *c = 1;
+        } else if record[PLANE_BYTES + i / 8] & bit != 0 {
+            *c = -1;
+        }
+    }
+    let mut pos = PLANE_BYTES * 2;
+    let probs: Vec<f32> = (0..MOVE_LEN)
+        .map(|i| {
+            let b = [record[pos + i * 2], record[pos + i * 2 + 1]];
+            u16::from_le_bytes(b) as f32 / PI_SCALE
+        })
+        .collect();
+    pos += MOVE_LEN * 2;
+    let f32_at = |pos: usize| f32::from_le_bytes(record[pos..pos + 4].try_into().unwrap());
+    TrainExample {
+        pi: Pi::new(&probs),
+        canonical_board: board,
+        player: if record[pos + 1] as i8 == 1 {
+            Player::PLAYER1
+        } else {
+            Player::PLAYER2
+        },
+        result: record[pos] as i8 as i32,
+        _turn: Turn(u16::from_le_bytes([record[pos + 2], record[pos + 3]]) as usize),
+        //フルサーチした手だけを書く
+        is_full_search: true,
+        _q: f32_at(pos + 4),
+        value_target: f32_at(pos + 8),
+        score: f32_at(pos + 12),
+    }
+}
+
+/// ファイルの中のチャンクの位置
+#[derive(Debug, Clone, Copy)]
+struct ChunkRef {
+    file: usize,
+    /// レコードの始まり
+    offset: u64,
+    count: usize,
+}
+
+/// ヘッダを確かめ、読み切れるチャンクを返す。2つ目は読み切れた範囲の終わり
+fn scan_chunks(file: &mut File, file_index: usize) -> io::Result<(Vec<ChunkRef>, u64)> {
+    let len = file.metadata()?.len();
+    let mut h = [0; HEADER_SIZE];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut h)?;
+    check_header(&h)?;
+    let mut chunks = vec![];
+    let mut offset = HEADER_SIZE as u64;
+    while offset + CHUNK_HEADER_SIZE as u64 <= len {
+        let mut c = [0; CHUNK_HEADER_SIZE];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut c)?;
+        let count = u32::from_le_bytes(c) as usize;
+        let start = offset + CHUNK_HEADER_SIZE as u64;
+        let end = start + (count * RECORD_SIZE) as u64;
+        if len < end {
+            break;
+        }
+        chunks.push(ChunkRef {
+            file: file_index,
+            offset: start,
+            count,
+        });
+        offset = end;
+    }
+    Ok((chunks, offset))
+}
+
+/// 教師データをchunk_size個ずつチャンクにしてファイルの末尾に書き足す。
+/// 既存のファイルに書きかけのチャンクが残っていたら切り詰めてから書く
+pub struct ExampleWriter {
+    file: BufWriter<File>,
+    chunk_size: usize,
+    pending: Vec<u8>,
+    pending_count: usize,
+}
+
+impl ExampleWriter {
+    pub fn open<P: AsRef<Path>>(path: P, chunk_size: usize) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(&header())?;
+        } else {
+            let (_, end) = scan_chunks(&mut file, 0)?;
+            file.set_len(end)?;
+            file.seek(SeekFrom::Start(end))?;
+        }
+        Ok(Self {
+            file: BufWriter::new(file),
+            chunk_size: chunk_size.max(1),
+            pending: Vec::with_capacity(chunk_size * RECORD_SIZE),
+            pending_count: 0,
+        })
+    }
+
+    /// フルサーチした手だけを書く(playout cap randomization)
+    pub fn write_game(&mut self, examples: &[TrainExample]) -> io::Result<()> {
+        for example in examples.iter().filter(|e| e.is_full_search) {
+            encode(example, &mut self.pending);
+            self.pending_count += 1;
+            if self.pending_count == self.chunk_size {
+                self.flush_chunk()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 溜まっている教師データを一つのチャンクとして書き出す
+    pub fn flush_chunk(&mut self) -> io::Result<()> {
+        if self.pending_count == 0 {
+            return Ok(());
+        }
+        self.file
+            .write_all(&(self.pending_count as u32).to_le_bytes())?;
+        self.file.write_all(&self.pending)?;
+        self.file.flush()?;
+        self.pending.clear();
+        self.pending_count = 0;
+        Ok(())
+    }
+}
+
+impl Drop for ExampleWriter {
+    fn drop(&mut self) {
+        let _ = self.flush_chunk();
+    }
+}
+
+/// 教師データのファイルを読み、ミニバッチにして返す。
+/// shuffle_chunksが0ならファイルの順に読む。
+/// 1以上ならチャンクの順をシャッフルし、shuffle_chunks個のチャンクを読み込んで混ぜてから返す
+pub struct ExampleReader {
+    files: Vec<File>,
+    chunks: Vec<ChunkRef>,
+    order: Vec<usize>,
+    next_chunk: usize,
+    buffer: Vec<TrainExample>,
+    shuffle_chunks: usize,
+    rng: StdRng,
+    batch: ExampleBatch,
+}
+
+impl ExampleReader {
+    pub fn open<P: AsRef<Path>>(paths: &[P], shuffle_chunks: usize, seed: u64) -> io::Result<Self> {
+        let mut files = vec![];
+        let mut chunks = vec![];
+        for (index, path) in paths.iter().enumerate() {
+            let mut file = File::open(path)?;
+            chunks.extend(scan_chunks(&mut file, index)?.0);
+            files.push(file);
+        }
+        let mut reader = Self {
+            files,
+            order: (0..chunks.len()).collect(),
+            chunks,
+            next_chunk: 0,
+            buffer: vec![],
+            shuffle_chunks,
+            rng: StdRng::seed_from_u64(seed),
+            batch: ExampleBatch::default(),
+        };
+        reader.reset();
+        Ok(reader)
+    }
+
+    /// 全ファイルの教師データの数
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|c| c.count).sum()
+    }
+
+    /// 最初から読み直す。シャッフルするならチャンクの順も変える
+    pub fn reset(&mut self) {
+        self.next_chunk = 0;
+        self.buffer.clear();
+        if 0 < self.shuffle_chunks {
+            self.order.shuffle(&mut self.rng);
+        }
+    }
+
+    /// 次のbatch_size個を読み、読んだ数を返す。最後は足りない分だけ少なくなり、読み終わったら0
+    pub fn next_batch(&mut self, batch_size: usize) -> io::Result<usize> {
+        while self.buffer.len() < batch_size && self.next_chunk < self.order.len() {
+            for _ in 0..self.shuffle_chunks.max(1) {
+                if self.next_chunk == self.order.len() {
+                    break;
+                }
+                let chunk = self.chunks[self.order[self.next_chunk]];
+                self.next_chunk += 1;
+                self.read_chunk(chunk)?;
+            }
+            if 0 < self.shuffle_chunks {
+                self.buffer.shuffle(&mut self.rng);
+            }
+        }
+        let amount = batch_size.min(self.buffer.len());
+        self.batch = ExampleBatch::new(self.buffer.drain(..amount).collect());
+        Ok(amount)
+    }
+
+    /// 全チャンクを順に読んで同じ局面の教師データをまとめ、一つのバッチにする。まとめた後の数を返す。
+    /// 読む位置は最初に戻る
+    pub fn dedup_all(&mut self, symmetry: bool) -> io::Result<usize> {
+        let mut deduplicator = Deduplicator::new(symmetry);
+        for index in 0..self.chunks.len() {
+            self.buffer.clear();
+            self.read_chunk(self.chunks[index])?;
+            for example in &self.buffer {
+                deduplicator.add(example);
+            }
+        }
+        self.reset();
+        self.batch = deduplicator.finish();
+        Ok(self.batch.len())
+    }
+
+    pub fn batch(&self) -> &ExampleBatch {
+        &self.batch
+    }
+
+    fn read_chunk(&mut self, chunk: ChunkRef) -> io::Result<()> {
+        let file = &mut self.files[chunk.file];
+        let mut bytes = vec![0; chunk.count * RECORD_SIZE];
+        file.seek(SeekFrom::Start(chunk.offset))?;
+        file.read_exact(&mut bytes)?;
+        self.buffer
+            .extend(bytes.chunks_exact(RECORD_SIZE).map(decode));
+        Ok(())
+    }
+}
+
+/// pathsは改行区切りのファイルのパス。seedはシャッフルに使う。
+/// 開けなければNULL POINTER(0)を返し、errorがNULL POINTERでなければdestroy_c_stringで解放する理由を書く
+#[no_mangle]
+pub extern "C" fn create_example_reader(
+    paths: *const c_char,
+    shuffle_chunks: usize,
+    seed: u64,
+    error: *mut *mut c_char,
+) -> *mut ExampleReader {
+    let opened = unsafe { CStr::from_ptr(paths) }
+        .to_str()
+        .map_err(|e| e.to_string())
+        .and_then(|paths| {
+            let paths: Vec<&str> = paths.lines().filter(|p| !p.is_empty()).collect();
+            ExampleReader::open(&paths, shuffle_chunks, seed).map_err(|e| e.to_string())
+        });
+    match opened {
+        Ok(reader) => Box::into_raw(Box::new(reader)),
+        Err(message) => {
+            write_c_error(error, &message);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn destroy_example_reader(p: *mut ExampleReader) {
+    unsafe {
+        let _ = Box::from_raw(p);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_len(p: *mut ExampleReader) -> usize {
+    unsafe { (*p).len() }
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_reset(p: *mut ExampleReader) {
+    unsafe { (*p).reset() }
+}
+
+/// 読んだ数を返す。読み終わったか、読めなければ0
+#[no_mangle]
+pub extern "C" fn example_reader_next_batch(p: *mut ExampleReader, batch_size: usize) -> usize {
+    unsafe { (*p).next_batch(batch_size).unwrap_or(0) }
+}
+
+/// 全チャンクの同じ局面の教師データをまとめて一つのバッチにし、その数を返す。読めなければ0
+#[no_mangle]
+pub extern "C" fn example_reader_dedup_all(p: *mut ExampleReader, symmetry: bool) -> usize {
+    unsafe { (*p).dedup_all(symmetry).unwrap_or(0) }
+}
+
+fn batch_array<F>(p: *mut ExampleReader, f: F) -> *mut CArray<f32>
+where
+    F: Fn(&ExampleBatch) -> CArray<f32>,
+{
+    unsafe { Box::into_raw(Box::new(f((*p).batch()))) }
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_get_pis(p: *mut ExampleReader) -> *mut CArray<f32> {
+    batch_array(p, ExampleBatch::get_pis)
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_get_boards(p: *mut ExampleReader) -> *mut CArray<f32> {
+    batch_array(p, ExampleBatch::get_boards)
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_get_players(p: *mut ExampleReader) -> *mut CArray<f32> {
+    batch_array(p, ExampleBatch::get_players)
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_get_value_targets(p: *mut ExampleReader) -> *mut CArray<f32> {
+    batch_array(p, ExampleBatch::get_value_targets)
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_get_scores(p: *mut ExampleReader) -> *mut CArray<f32> {
+    batch_array(p, ExampleBatch::get_scores)
+}
+
+#[no_mangle]
+pub extern "C" fn example_reader_get_weights(p: *mut ExampleReader) -> *mut CArray<f32> {
+    batch_array(p, ExampleBatch::get_weights)
+}
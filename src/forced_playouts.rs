@@ -0,0 +1,75 @@
+use crate::action::Pi;
+use crate::constant::MOVE_LEN;
+use crate::evaluator::Evaluator;
+use crate::mcts::{BoardState, Mcts};
+use crate::node_action_params::NodeInfo;
+use crate::othello_board::OthelloBoard;
+use crate::player::Player;
+
+/// Forced playouts(KataGo)でルートの子に強制する最低訪問回数 sqrt(k * P * N)
+pub fn forced_visits(k: f32, node_info: &NodeInfo, a: usize) -> f32 {
+    (k * node_info.predicted_pi[a] * node_info.count as f32).sqrt()
+}
+
+impl<'a, E: Evaluator + ?Sized> Mcts<'a, E> {
+    /// Policy target pruning。最も訪問された手以外から、PUCTなら選ばれなかった強制訪問を取り除いた訪問回数
+    pub fn pruned_counts(&self, s: u128) -> Vec<usize> {
+        let mut counts = self.root_counts(s);
+        let Some(node_info) = self.node.get(&s) else {
+            return counts;
+        };
+        let Some(best) = (0..MOVE_LEN).max_by_key(|&a| counts[a]) else {
+            return counts;
+        };
+        if counts[best] == 0 {
+            return counts;
+        }
+        let sqrt_total = (node_info.count as f32).sqrt();
+        let puct = |a: usize, count: usize, q: f32| {
+            q + self.args.cpuct * node_info.predicted_pi[a] * sqrt_total / (1.0 + count as f32)
+        };
+        let best_q = self.node_act[&BoardState::new(s, best)].win_rate;
+        let best_puct = puct(best, counts[best], best_q);
+
+        for (a, visits) in counts.iter_mut().enumerate() {
+            if a == best || *visits == 0 {
+                continue;
+            }
+            let q = self.node_act[&BoardState::new(s, a)].win_rate;
+            let forced = forced_visits(self.args.forced_playouts_k, node_info, a).floor() as usize;
+            //減らしてもPUCTの値が最善手を下回る(PUCTなら選ばれなかった)範囲で訪問回数を減らす
+            let mut count = *visits;
+            while 0 < count && *visits - count < forced && puct(a, count - 1, q) < best_puct {
+                count -= 1;
+            }
+            //1回しか訪問されていない手は、ほぼ強制された訪問なので取り除く
+            *visits = if count <= 1 { 0 } else { count };
+        }
+        counts
+    }
+
+    /// 方策の教師データ。指し手はmove_piから選び、pruningは教師データだけに使う。
+    /// temp == 0や勝ちが証明された場合はmove_piのまま
+    pub fn policy_target(
+        &self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        move_pi: &Pi,
+        temp: f32,
+    ) -> Pi {
+        let s = unorthodox_board
+            .create_canonical_board(player)
+            .string_representation();
+        if !self.args.policy_target_pruning || temp == 0.0 || self.proven_win_action(s).is_some() {
+            return move_pi.clone();
+        }
+        let counts: Vec<f32> = self
+            .pruned_counts(s)
+            .iter()
+            .map(|&c| (c as f32).powf(1.0 / temp))
+            .collect();
+        let sum: f32 = counts.iter().sum();
+        let probs: Vec<f32> = counts.iter().map(|&c| c / sum).collect();
+        Pi::new(&probs)
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use rand::Rng;
+
+use crate::action::{Action, Pi};
+use crate::constant::{EPS, MOVE_LEN};
+use crate::evaluator::Evaluator;
+use crate::mcts::{BoardState, Mcts, Turn};
+use crate::othello_board::OthelloBoard;
+use crate::othello_game::get_next_state;
+use crate::player::Player;
+use crate::search_limit::SearchLimit;
+
+/// 評価待ちで中断したSequential Halvingの途中経過
+pub(crate) struct GumbelProgress {
+    logits: Vec<f32>,
+    gumbel: [f32; MOVE_LEN],
+    candidates: Vec<usize>,
+    total: usize,
+    sims_left: usize,
+    num_phases: usize,
+    /// 今のフェーズでこれから訪問するルートの子
+    queue: VecDeque<usize>,
+    /// 時間制限の場合、これを過ぎたらシミュレーションが残っていても打ち切る
+    deadline: Option<Instant>,
+}
+
+/// Gumbel AlphaZero(Danihelka et al. 2022)のルート探索。ルートより下は通常のsearchを使う
+impl<'a, E: Evaluator + ?Sized> Mcts<'a, E> {
+    /// 指し手と、Completed Q-valueから作った改善方策を返す。評価待ちで中断したらNone
+    ///
+    /// temp == 0ならGumbelノイズを使わず、最も良い手を選ぶ
+    pub fn get_action_gumbel(
+        &mut self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        turn: Turn,
+        temp: f32,
+        limit: SearchLimit,
+    ) -> Option<(Action, Pi)> {
+        let canonical_board = unorthodox_board.create_canonical_board(player);
+        let s = canonical_board.string_representation();
+        self.suspended = false;
+
+        let mut progress = match self.progress.gumbel.take() {
+            Some(progress) => progress,
+            None => self.start_gumbel(unorthodox_board, player, turn, temp, limit, s)?,
+        };
+
+        //Sequential Halving
+        while 0 < progress.sims_left {
+            if progress.queue.is_empty() {
+                let per_action =
+                    (progress.total / (progress.num_phases * progress.candidates.len())).max(1);
+                for &a in &progress.candidates {
+                    progress.queue.extend(std::iter::repeat_n(a, per_action));
+                }
+            }
+            while let Some(&a) = progress.queue.front() {
+                if progress.deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+                    progress.sims_left = 0;
+                }
+                if progress.sims_left == 0 {
+                    break;
+                }
+                self.visit_root_child(unorthodox_board, player, turn, s, a);
+                if self.suspended {
+                    self.progress.gumbel = Some(progress);
+                    return None;
+                }
+                progress.queue.pop_front();
+                progress.sims_left -= 1;
+                self.count_simulation();
+                if self.is_cancelled() {
+                    self.progress.gumbel = Some(progress);
+                    return None;
+                }
+            }
+            progress.queue.clear();
+            if 1 < progress.candidates.len() {
+                let scores = self.gumbel_scores(s, &progress.gumbel, &progress.logits);
+                progress
+                    .candidates
+                    .sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+                progress
+                    .candidates
+                    .truncate(progress.candidates.len().div_ceil(2));
+            }
+        }
+
+        if let Some(a) = self.proven_win_action(s) {
+            let mut probs = vec![0.0; MOVE_LEN];
+            probs[a] = 1.0;
+            return Some((Action::new(a), Pi::new(&probs)));
+        }
+
+        let scores = self.gumbel_scores(s, &progress.gumbel, &progress.logits);
+        //ルートの展開だけで終わり、どの候補も訪問していなければ、gumbel + logitsで選ぶ
+        let scores = if progress.candidates.iter().all(|&a| scores[a] == f32::NEG_INFINITY) {
+            std::array::from_fn(|a| progress.gumbel[a] + progress.logits[a])
+        } else {
+            scores
+        };
+        let action = *progress
+            .candidates
+            .iter()
+            .max_by(|&&a, &&b| scores[a].total_cmp(&scores[b]))
+            .unwrap();
+
+        Some((
+            Action::new(action),
+            self.improved_policy(s, &progress.logits),
+        ))
+    }
+
+    /// ルートを展開し、Gumbelノイズで候補手を選ぶ
+    fn start_gumbel(
+        &mut self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        turn: Turn,
+        temp: f32,
+        limit: SearchLimit,
+        s: u128,
+    ) -> Option<GumbelProgress> {
+        let mut sims_left = self.limit_to_sims(limit, s).max(1);
+        if !self.node.contains_key(&s) {
+            //ルートを展開する。これも1回のシミュレーションとして数える
+            self.search(unorthodox_board, player, turn);
+            if self.suspended {
+                return None;
+            }
+            sims_left -= 1;
+            self.count_simulation();
+        }
+
+        let node_info = &self.node[&s];
+        let logits: Vec<f32> = (0..MOVE_LEN)
+            .map(|a| (node_info.predicted_pi[a] + EPS).ln())
+            .collect();
+        let valids: Vec<usize> = (0..MOVE_LEN)
+            .filter(|&a| node_info.valid_moves[a])
+            .collect();
+
+        let mut gumbel = [0.0f32; MOVE_LEN];
+        if temp != 0.0 {
+            for &a in &valids {
+                let u: f32 = self.rng.gen::<f32>().max(EPS);
+                gumbel[a] = -(-u.ln()).ln();
+            }
+        }
+
+        let m = self
+            .args
+            .gumbel_num_sampled_actions
+            .min(valids.len())
+            .max(1);
+        let mut candidates = valids.clone();
+        candidates.sort_by(|&a, &b| (gumbel[b] + logits[b]).total_cmp(&(gumbel[a] + logits[a])));
+        candidates.truncate(m);
+
+        Some(GumbelProgress {
+            logits,
+            gumbel,
+            candidates,
+            total: sims_left,
+            sims_left,
+            num_phases: (m as f32).log2().ceil().max(1.0) as usize,
+            queue: VecDeque::new(),
+            deadline: match limit {
+                SearchLimit::Deadline(deadline) => Some(deadline),
+                _ => None,
+            },
+        })
+    }
+
+    fn visit_root_child(
+        &mut self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        turn: Turn,
+        s: u128,
+        a: usize,
+    ) {
+        let mut next_s = unorthodox_board.clone();
+        get_next_state(&mut next_s, player, Action::new(a));
+        let v = self.search(&next_s, player.other(), turn.next());
+        if self.suspended {
+            return;
+        }
+        self.node.get_mut(&s).unwrap().count += 1;
+        self.update_node_act(s, a, v);
+        self.backup_proven(s, a, &next_s, player.other());
+    }
+
+    /// (visit数, Q)。未訪問の手はNone
+    fn root_child_stats(&self, s: u128) -> [Option<(usize, f32)>; MOVE_LEN] {
+        let mut r = [None; MOVE_LEN];
+        for (a, item) in r.iter_mut().enumerate() {
+            if let Some(info) = self.node_act.get(&BoardState::new(s, a)) {
+                *item = Some((info.count, info.win_rate));
+            }
+        }
+        r
+    }
+
+    /// σ(q) = (c_visit + max_b N(b)) * c_scale * q 。qは[0, 1]に正規化したもの
+    fn sigma(&self, max_count: usize, q: f32) -> f32 {
+        let q = (q + 1.0) / 2.0;
+        (self.args.gumbel_c_visit + max_count as f32) * self.args.gumbel_c_scale * q
+    }
+
+    fn gumbel_scores(&self, s: u128, gumbel: &[f32], logits: &[f32]) -> [f32; MOVE_LEN] {
+        let stats = self.root_child_stats(s);
+        let max_count = stats.iter().flatten().map(|&(n, _)| n).max().unwrap_or(0);
+        let mut scores = [f32::NEG_INFINITY; MOVE_LEN];
+        for (a, stat) in stats.iter().enumerate() {
+            if let Some((_, q)) = stat {
+                scores[a] = gumbel[a] + logits[a] + self.sigma(max_count, *q);
+            }
+        }
+        scores
+    }
+
+    /// 未訪問の手のQをv_mixで補ったCompleted Q-valueで、softmax(logits + σ(completedQ))を作る
+    fn improved_policy(&self, s: u128, logits: &[f32]) -> Pi {
+        let node_info = &self.node[&s];
+        let stats = self.root_child_stats(s);
+        let max_count = stats.iter().flatten().map(|&(n, _)| n).max().unwrap_or(0);
+
+        let mut sum_n = 0.0;
+        let mut sum_p = 0.0;
+        let mut sum_pq = 0.0;
+        for (a, stat) in stats.iter().enumerate() {
+            if let Some((n, q)) = *stat {
+                sum_n += n as f32;
+                sum_p += node_info.predicted_pi[a];
+                sum_pq += node_info.predicted_pi[a] * q;
+            }
+        }
+        let v_root = node_info.predicted_win_rate;
+        let v_mix = if sum_n == 0.0 {
+            v_root
+        } else {
+            (v_root + sum_n / sum_p.max(EPS) * sum_pq) / (1.0 + sum_n)
+        };
+
+        let mut probs = vec![0.0; MOVE_LEN];
+        let mut max_logit = f32::NEG_INFINITY;
+        for (a, p) in probs.iter_mut().enumerate() {
+            if node_info.valid_moves[a] {
+                let q = stats[a].map_or(v_mix, |(_, q)| q);
+                *p = logits[a] + self.sigma(max_count, q);
+                max_logit = max_logit.max(*p);
+            }
+        }
+        let mut sum = 0.0;
+        for (a, p) in probs.iter_mut().enumerate() {
+            if node_info.valid_moves[a] {
+                *p = (*p - max_logit).exp();
+                sum += *p;
+            }
+        }
+        for p in probs.iter_mut() {
+            *p /= sum;
+        }
+        Pi::new(&probs)
+    }
+}
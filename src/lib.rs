@@ -1,16 +1,32 @@
 mod action;
+mod alpha_beta;
+mod analysis;
 mod c_array;
 mod constant;
+mod dedup;
+mod engine_config;
+mod eval_cache;
+mod example_batch;
+mod example_file;
+mod evaluator;
+mod forced_playouts;
+mod gumbel;
 mod mcts;
 mod mcts_args;
+mod opponent;
 mod othello_board;
 mod othello_game;
 mod player;
 mod predict_result;
 mod py_communicator;
+mod replay_buffer;
+mod search_limit;
+mod search_task;
 mod self_player;
+mod shared_tree;
 mod test_mcts;
 mod thread_id;
+mod tree_export;
 mod node_action_params;
 
 fn _main(){
@@ -1,18 +1,28 @@
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 use rand::distributions::WeightedIndex;
 use rand::prelude::Distribution;
+use rand::rngs::StdRng;
 use rand::Rng;
 
 use crate::action::{Action, Pi};
-use crate::constant::{EPS, MOVE_LEN};
-use crate::mcts_args::MctsArgs;
-use crate::node_action_params::{NodeActionInfo, NodeInfo};
+use crate::alpha_beta::AlphaBeta;
+use crate::analysis::{Analysis, AnalysisSlots};
+use crate::constant::{EPS, MOVE_LEN, N};
+use crate::evaluator::{BoxedEvaluator, Evaluator};
+use crate::forced_playouts::forced_visits;
+use crate::gumbel::GumbelProgress;
+use crate::mcts_args::{MctsArgs, RootSearch, SearchBudget};
+use crate::node_action_params::{NodeActionInfo, NodeInfo, Proven};
 use crate::othello_game::{get_game_ended, get_next_state, get_valid_moves};
 use crate::predict_result::PredictResult;
+use crate::search_limit::{GameClock, SearchLimit};
 use crate::thread_id::ThreadID;
+use crate::tree_export::TreeExportSlots;
 use crate::{othello_board::OthelloBoard, player::Player};
 
 use std::fmt::Write;
@@ -29,33 +39,116 @@ impl BoardState {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct TrainExample {
     pub pi: Pi,
     pub canonical_board: OthelloBoard,
     pub player: Player,
     pub result: i32,
     pub _turn: Turn,
+    /// フルサーチした手か。falseの手は方策の教師データにしない
+    pub is_full_search: bool,
+    /// playerから見たルートの探索Q
+    pub _q: f32,
+    /// resultとqをMctsArgs::value_targetで混ぜたもの
+    pub value_target: f32,
+    /// playerから見た最終的な石差をマスの数で割ったもの(-1..1)。投了した試合では投了時点の石差
+    pub score: f32,
+}
+
+/// 投了に関する記録。投了の閾値の調整に使う
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResignRecord {
+    /// 投了の閾値を下回らなかった
+    NotResigned,
+    Resigned,
+    /// 投了が禁止された試合で、投了の閾値を下回った。would_have_lostがfalseなら投了は誤りだった
+    Control {
+        would_have_lost: bool,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Episode {
+    pub examples: Vec<TrainExample>,
+    pub resign: ResignRecord,
 }
 
 pub struct MctsContext {
     pub player_mode: PlayerMode,
     pub p1_mcts_info: MctsInfo,
     pub p2_mcts_info: MctsInfo,
-    pub send_to_main: mpsc::Sender<ThreadToMain>,
-    pub receive_from_main: mpsc::Receiver<MainToThread>,
+    pub p1_evaluator: BoxedEvaluator,
+    /// 2Playerモードでのみ使う
+    pub p2_evaluator: Option<BoxedEvaluator>,
+    /// その手番の探索だけに使う設定。Noneならargsを使う
+    pub p1_args: Option<MctsArgs>,
+    pub p2_args: Option<MctsArgs>,
+    /// Someなら、その手番はMCTSではなくアルファベータ探索で指す
+    pub p1_alpha_beta: Option<AlphaBeta>,
+    pub p2_alpha_beta: Option<AlphaBeta>,
     pub thread_id: ThreadID,
     pub args: MctsArgs,
+    pub analysis_slots: AnalysisSlots,
+    /// Someなら、要求があったときにルートの探索木を書き出す
+    pub tree_exports: Option<TreeExportSlots>,
+    pub rng: StdRng,
+    /// trueになったら探索を打ち切り、試合を中断したままにする
+    cancel: Option<Arc<AtomicBool>>,
+    /// Someなら終わったシミュレーションの数を足していく
+    simulations: Option<Arc<AtomicUsize>>,
+    /// 評価待ちで中断している試合
+    episode: Option<EpisodeState>,
 }
 
-pub struct Mcts<'a> {
+/// 進行中の試合の状態。評価待ちで中断している間も保持する
+struct EpisodeState {
+    unorthodox_board: OthelloBoard,
+    cur_player: Player,
+    episode_step: usize,
+    train_examples: Vec<(Pi, Player, OthelloBoard, Turn, bool, f32)>,
+    resign_allowed: bool,
+    would_resign: Option<Player>,
+    clock: Option<GameClock>,
+    /// 探索の途中で中断した手
+    current_move: Option<MoveState>,
+}
+
+/// 一手分の探索の設定と途中経過
+struct MoveState {
+    turn: Turn,
+    temp: f32,
+    is_full_search: bool,
+    limit: SearchLimit,
+    move_start: Instant,
+    progress: SearchProgress,
+}
+
+pub struct Mcts<'a, E: Evaluator + ?Sized> {
     pub node_act: &'a mut HashMap<BoardState, NodeActionInfo>,
     pub node: &'a mut HashMap<u128, NodeInfo>,
     pub is_game_end: &'a mut HashMap<u128, i32>,
-    pub send_to_main: &'a mut mpsc::Sender<ThreadToMain>,
-    pub receive_from_main: &'a mut mpsc::Receiver<MainToThread>,
-    pub thread_id: &'a mut ThreadID,
+    pub evaluator: &'a mut E,
     pub args: &'a mut MctsArgs,
+    pub rng: &'a mut StdRng,
+    /// Forced playoutsを行うルート。PUCTの探索中だけSomeになる
+    pub(crate) forced_root: Option<u128>,
+    /// 評価器が評価を返さず、今のシミュレーションを中断した
+    pub(crate) suspended: bool,
+    /// 中断した一手分の探索の途中経過
+    pub(crate) progress: SearchProgress,
+    /// trueになったらシミュレーションを打ち切り、評価待ちと同じように中断する
+    pub(crate) cancel: Option<&'a AtomicBool>,
+    /// Someなら終わったシミュレーションの数を足していく
+    pub(crate) simulations: Option<&'a AtomicUsize>,
+}
+
+/// 評価待ちで中断した一手分の探索の途中経過。同じ局面の探索を再開するときにMctsに戻す
+#[derive(Default)]
+pub(crate) struct SearchProgress {
+    start: Option<Instant>,
+    sims_done: usize,
+    pub(crate) gumbel: Option<GumbelProgress>,
 }
 
 ///Player1とPlayer2で思考担当が違う場合があり、その場合別々のデータが必要になる
@@ -76,8 +169,10 @@ impl MctsInfo {
 }
 
 pub enum ThreadToMain {
-    Board(OthelloBoard, ThreadID, Player, Turn),
-    TrainExamples(Vec<TrainExample>, ThreadID),
+    Board(OthelloBoard, ThreadID, Player),
+    TrainExamples(Episode, ThreadID),
+    /// このスロットではもう試合を始めない
+    Closed(ThreadID),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -90,7 +185,7 @@ impl Turn {
 }
 
 pub enum MainToThread {
-    Prediction(PredictResult),
+    Prediction(PredictResult, ThreadID),
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -104,165 +199,442 @@ pub enum PlayerMode {
 impl MctsContext {
     pub fn new(
         player_mode: PlayerMode,
-        send_to_main: mpsc::Sender<ThreadToMain>,
-        receive_from_main: mpsc::Receiver<MainToThread>,
+        p1_evaluator: BoxedEvaluator,
+        p2_evaluator: Option<BoxedEvaluator>,
         thread_id: ThreadID,
         args: MctsArgs,
+        analysis_slots: AnalysisSlots,
     ) -> Self {
+        if player_mode == PlayerMode::_2Player && p2_evaluator.is_none() {
+            panic!("2Player mode needs p2_evaluator");
+        }
+        let rng = thread_id.create_rng(args.seed);
         Self {
             player_mode,
             p1_mcts_info: MctsInfo::new(),
             p2_mcts_info: MctsInfo::new(),
-            send_to_main,
-            receive_from_main,
+            p1_evaluator,
+            p2_evaluator,
+            p1_args: None,
+            p2_args: None,
+            p1_alpha_beta: None,
+            p2_alpha_beta: None,
             thread_id,
             args,
+            analysis_slots,
+            tree_exports: None,
+            rng,
+            cancel: None,
+            simulations: None,
+            episode: None,
+        }
+    }
+
+    /// cancelがtrueになったら、探索中の手も含めて試合を止める。アルファベータ探索にも伝える
+    pub fn set_cancel(&mut self, cancel: Arc<AtomicBool>) {
+        for alpha_beta in [&mut self.p1_alpha_beta, &mut self.p2_alpha_beta]
+            .into_iter()
+            .flatten()
+        {
+            alpha_beta.set_cancel(cancel.clone());
+        }
+        self.cancel = Some(cancel);
+    }
+
+    /// 全スロットのシミュレーションの数を数える(自己対戦の進み具合を見るため)
+    pub fn set_simulation_counter(&mut self, simulations: Arc<AtomicUsize>) {
+        self.simulations = Some(simulations);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// playerの手番の探索だけ別の設定にする(UCTなどの対戦相手用)
+    pub fn set_player_args(&mut self, player: Player, args: MctsArgs) {
+        if player == Player::PLAYER1 {
+            self.p1_args = Some(args);
+        } else {
+            self.p2_args = Some(args);
+        }
+    }
+
+    /// playerの手番をアルファベータ探索で指す
+    pub fn set_player_alpha_beta(&mut self, player: Player, mut alpha_beta: AlphaBeta) {
+        if let Some(cancel) = &self.cancel {
+            alpha_beta.set_cancel(cancel.clone());
+        }
+        if player == Player::PLAYER1 {
+            self.p1_alpha_beta = Some(alpha_beta);
+        } else {
+            self.p2_alpha_beta = Some(alpha_beta);
         }
     }
 
-    pub fn execute_episode(&mut self) -> Vec<TrainExample> {
-        let mut unorthodox_board = OthelloBoard::initial_board();
-        let mut cur_player = Player::PLAYER1;
-        let mut episode_step: usize = 0;
-        let mut train_examples: Vec<(Pi, Player, OthelloBoard, Turn)> = vec![];
+    pub fn execute_episode(&mut self) -> Episode {
+        self.resume_episode()
+            .expect("evaluator suspended the search in execute_episode")
+    }
+
+    fn start_episode(&mut self) -> EpisodeState {
+        EpisodeState {
+            unorthodox_board: OthelloBoard::initial_board(),
+            cur_player: Player::PLAYER1,
+            episode_step: 0,
+            train_examples: vec![],
+            //一定の割合の試合では投了を禁止し、投了していたら本当に負けていたかを調べる
+            resign_allowed: self.args.resign_disabled_fraction <= self.rng.gen::<f32>(),
+            would_resign: None,
+            clock: match self.args.search_budget {
+                SearchBudget::GameClock {
+                    main_time,
+                    increment,
+                } => Some(GameClock::new(main_time, increment)),
+                _ => None,
+            },
+            current_move: None,
+        }
+    }
+
+    /// 試合を進める。評価器が評価を返さずに探索が中断したらNoneを返す。
+    /// 評価を用意してからもう一度呼ぶと、中断したところから続ける。
+    /// 中止されたときもNoneを返す(is_cancelledで区別する)
+    pub fn resume_episode(&mut self) -> Option<Episode> {
+        let mut episode = match self.episode.take() {
+            Some(episode) => episode,
+            None => self.start_episode(),
+        };
         loop {
-            episode_step += 1;
-            let turn = Turn(episode_step);
-            let temp = ((episode_step as i32) < self.args.temp_threshold) as u32 as f32;
+            if self.is_cancelled() {
+                self.episode = Some(episode);
+                return None;
+            }
+            let cur_player = episode.cur_player;
+            let temp_threshold = self.args.temp_threshold;
+            let is_p1 = self.player_mode == PlayerMode::_1Player || cur_player == Player::PLAYER1;
+            let player_args = if is_p1 {
+                &mut self.p1_args
+            } else {
+                &mut self.p2_args
+            };
+            let search_args = player_args.as_mut().unwrap_or(&mut self.args);
+            //UCTの相手は学習側の閾値では投了しない
+            let resign_threshold = search_args.resign_threshold;
+            let mut current = match episode.current_move.take() {
+                Some(current) => current,
+                None => {
+                    episode.episode_step += 1;
+                    let is_full_search = self.rng.gen::<f32>() < search_args.full_search_prob;
+                    let limit = if is_full_search {
+                        SearchLimit::from_args(
+                            search_args,
+                            episode.clock.as_ref(),
+                            &episode.unorthodox_board,
+                            cur_player,
+                        )
+                    } else {
+                        SearchLimit::Simulations(search_args.fast_num_mcts_sims.max(0) as usize)
+                    };
+                    MoveState {
+                        turn: Turn(episode.episode_step),
+                        temp: ((episode.episode_step as i32) < temp_threshold) as u32 as f32,
+                        is_full_search,
+                        limit,
+                        move_start: Instant::now(),
+                        progress: SearchProgress::default(),
+                    }
+                }
+            };
+            let turn = current.turn;
+            let unorthodox_board = &episode.unorthodox_board;
 
-            let mut mcts =
-                if self.player_mode == PlayerMode::_1Player || cur_player == Player::PLAYER1 {
+            let alpha_beta = if is_p1 {
+                &mut self.p1_alpha_beta
+            } else {
+                &mut self.p2_alpha_beta
+            };
+            let (action, pi, analysis) = if let Some(alpha_beta) = alpha_beta {
+                alpha_beta.decide_move(unorthodox_board, cur_player)
+            } else {
+                let mut mcts = if is_p1 {
                     Mcts::new(
                         &mut self.p1_mcts_info.node_act,
                         &mut self.p1_mcts_info.node,
                         &mut self.p1_mcts_info.is_game_end,
-                        &mut self.send_to_main,
-                        &mut self.receive_from_main,
-                        &mut self.thread_id,
-                        &mut self.args,
+                        self.p1_evaluator.as_mut(),
+                        search_args,
+                        &mut self.rng,
                     )
                 } else {
                     Mcts::new(
                         &mut self.p2_mcts_info.node_act,
                         &mut self.p2_mcts_info.node,
                         &mut self.p2_mcts_info.is_game_end,
-                        &mut self.send_to_main,
-                        &mut self.receive_from_main,
-                        &mut self.thread_id,
-                        &mut self.args,
+                        self.p2_evaluator.as_mut().unwrap().as_mut(),
+                        search_args,
+                        &mut self.rng,
                     )
                 };
+                mcts.progress = std::mem::take(&mut current.progress);
+                mcts.cancel = self.cancel.as_deref();
+                mcts.simulations = self.simulations.as_deref();
+                let Some(decided) = mcts.decide_move(
+                    unorthodox_board,
+                    cur_player,
+                    turn,
+                    current.temp,
+                    current.limit,
+                ) else {
+                    current.progress = std::mem::take(&mut mcts.progress);
+                    episode.current_move = Some(current);
+                    self.episode = Some(episode);
+                    return None;
+                };
+                if let Some(tree_exports) = &self.tree_exports {
+                    if let Some((max_depth, min_visits)) =
+                        tree_exports.take_request(&self.thread_id)
+                    {
+                        let tree =
+                            mcts.export_tree(unorthodox_board, cur_player, max_depth, min_visits);
+                        tree_exports.fulfill(&self.thread_id, tree);
+                    }
+                }
+                decided
+            };
+            let q = analysis.root_value;
+            self.analysis_slots.set(&self.thread_id, analysis);
+            if let Some(clock) = &mut episode.clock {
+                clock.consume(cur_player, current.move_start.elapsed());
+            }
 
-            let pi = mcts.get_action_prob(&unorthodox_board, cur_player, turn, temp);
-
-            train_examples.push((
-                pi.clone(),
-                cur_player.clone(),
+            episode.train_examples.push((
+                pi,
+                cur_player,
                 unorthodox_board.create_canonical_board(cur_player),
                 turn,
+                current.is_full_search,
+                q,
             ));
 
-            let mut rng = rand::thread_rng();
+            let resigns = q < resign_threshold;
+            if resigns && !episode.resign_allowed && episode.would_resign.is_none() {
+                episode.would_resign = Some(cur_player);
+            }
 
-            let dist = WeightedIndex::new(pi.probs()).unwrap();
-            let action = dist.sample(&mut rng);
-            get_next_state(&mut unorthodox_board, cur_player, Action::new(action));
+            let r = if resigns && episode.resign_allowed {
+                //投了したプレイヤーの負け。cur_playerは相手になる
+                episode.cur_player = cur_player.other();
+                1
+            } else {
+                get_next_state(&mut episode.unorthodox_board, cur_player, action);
 
-            cur_player = cur_player.other();
+                episode.cur_player = cur_player.other();
 
-            let r = get_game_ended(&unorthodox_board, cur_player);
+                get_game_ended(&episode.unorthodox_board, episode.cur_player)
+            };
 
             if r != 0 {
-                let result: Vec<TrainExample> = train_examples
-                    .into_iter()
-                    .map(|(pi, player, canonical_board, _turn)| {
-                        let result = r * (-1i32).pow((player != cur_player) as u32);
-                        TrainExample {
-                            pi,
-                            player,
-                            canonical_board,
-                            result,
-                            _turn,
-                        }
-                    })
-                    .collect();
-
-                fn _get_data_to_print(
-                    result: &Vec<TrainExample>,
-                ) -> Result<String, std::fmt::Error> {
-                    let mut print = String::new();
-                    let p = &mut print;
-
-                    writeln!(p, "----------TrainExample-----------")?;
-                    for item in result {
-                        let normal_board = item.canonical_board.create_canonical_board(item.player);
-                        writeln!(p, "{}", normal_board._to_string())?;
-                        writeln!(p, "{}", item.pi._to_string())?;
-                        let diff = normal_board.count_diff(Player::PLAYER1);
-                        writeln!(
-                            p,
-                            "Turn {} Player {} result {} Black {}",
-                            item._turn.0,
-                            item.player.color(),
-                            item.result,
-                            diff
-                        )?
-                    }
-                    Ok(print)
+                return Some(self.finish_episode(episode, r, resigns));
+            }
+        }
+    }
+
+    /// rは終局時の手番(cur_player)から見た勝敗
+    fn finish_episode(&self, episode: EpisodeState, r: i32, resigns: bool) -> Episode {
+        let cur_player = episode.cur_player;
+        let episode_step = episode.episode_step;
+        let margin = episode.unorthodox_board.count_diff(Player::PLAYER1) as f32 / (N * N) as f32;
+        let result: Vec<TrainExample> = episode
+            .train_examples
+            .into_iter()
+            .map(|(pi, player, canonical_board, _turn, is_full_search, q)| {
+                let result = r * (-1i32).pow((player != cur_player) as u32);
+                let value_target =
+                    self.args
+                        .value_target
+                        .mix(result as f32, q, _turn.0, episode_step);
+                TrainExample {
+                    pi,
+                    player,
+                    canonical_board,
+                    result,
+                    _turn,
+                    is_full_search,
+                    _q: q,
+                    value_target,
+                    score: margin * player.color() as f32,
                 }
+            })
+            .collect();
 
-                //println!("{}", get_data_to_print(&result).unwrap());
+        fn _get_data_to_print(result: &Vec<TrainExample>) -> Result<String, std::fmt::Error> {
+            let mut print = String::new();
+            let p = &mut print;
+
+            writeln!(p, "----------TrainExample-----------")?;
+            for item in result {
+                let normal_board = item.canonical_board.create_canonical_board(item.player);
+                writeln!(p, "{}", normal_board._to_string())?;
+                writeln!(p, "{}", item.pi._to_string())?;
+                let diff = normal_board.count_diff(Player::PLAYER1);
+                writeln!(
+                    p,
+                    "Turn {} Player {} result {} Black {}",
+                    item._turn.0,
+                    item.player.color(),
+                    item.result,
+                    diff
+                )?
+            }
+            Ok(print)
+        }
 
-                return result;
+        //println!("{}", get_data_to_print(&result).unwrap());
+
+        let resign = if resigns && episode.resign_allowed {
+            ResignRecord::Resigned
+        } else if let Some(p) = episode.would_resign {
+            ResignRecord::Control {
+                would_have_lost: r * (-1i32).pow((p != cur_player) as u32) < 0,
             }
+        } else {
+            ResignRecord::NotResigned
+        };
+
+        Episode {
+            examples: result,
+            resign,
         }
     }
 }
 
-impl<'a> Mcts<'a> {
+impl<'a, E: Evaluator + ?Sized> Mcts<'a, E> {
     pub fn new(
         node_act: &'a mut HashMap<BoardState, NodeActionInfo>,
         node: &'a mut HashMap<u128, NodeInfo>,
         is_game_end: &'a mut HashMap<u128, i32>,
-        send_to_main: &'a mut mpsc::Sender<ThreadToMain>,
-        receive_from_main: &'a mut mpsc::Receiver<MainToThread>,
-        thread_id: &'a mut ThreadID,
+        evaluator: &'a mut E,
         args: &'a mut MctsArgs,
+        rng: &'a mut StdRng,
     ) -> Self {
         Self {
             node_act,
             node,
             is_game_end,
-            send_to_main,
-            receive_from_main,
-            thread_id,
+            evaluator,
             args,
+            rng,
+            forced_root: None,
+            suspended: false,
+            progress: SearchProgress::default(),
+            cancel: None,
+            simulations: None,
         }
     }
 
-    pub fn get_action_prob(
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn count_simulation(&self) {
+        if let Some(simulations) = self.simulations {
+            simulations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 指し手と、方策の教師データとなるPiと、ルートの解析結果を返す。
+    /// 評価待ちで中断したらNone。progressを残したまま同じ引数で呼ぶと続きから探索する
+    pub fn decide_move(
         &mut self,
         unorthodox_board: &OthelloBoard,
         player: Player,
         turn: Turn,
         temp: f32,
-    ) -> Pi {
-        for _ in 0..self.args.num_mcts_sims {
-            self.search(unorthodox_board, player, player, turn);
+        limit: SearchLimit,
+    ) -> Option<(Action, Pi, Analysis)> {
+        match self.args.root_search {
+            RootSearch::Puct => {
+                let (pi, analysis) = self.get_action_prob_with_analysis(
+                    unorthodox_board,
+                    player,
+                    turn,
+                    temp,
+                    limit,
+                )?;
+                let dist = WeightedIndex::new(pi.probs()).unwrap();
+                let action = dist.sample(self.rng);
+                let target = self.policy_target(unorthodox_board, player, &pi, temp);
+                Some((Action::new(action), target, analysis))
+            }
+            RootSearch::Gumbel => {
+                let (action, pi) =
+                    self.get_action_gumbel(unorthodox_board, player, turn, temp, limit)?;
+                Some((action, pi, self.analyze(unorthodox_board, player)))
+            }
         }
+    }
 
+    /// 指し手を選ぶ確率。強制訪問も含めた訪問回数から作る(教師データはpolicy_target)。
+    /// 評価待ちで中断したらNone
+    pub fn get_action_prob(
+        &mut self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        turn: Turn,
+        temp: f32,
+        limit: SearchLimit,
+    ) -> Option<Pi> {
         let canonical_board = unorthodox_board.create_canonical_board(player);
         let s = canonical_board.string_representation();
 
-        let counts: Vec<usize> = (0..MOVE_LEN)
-            .map(|a| {
-                if let Some(info) = self.node_act.get(&BoardState::new(s, a)) {
-                    info.count
-                } else {
-                    0
+        let start = *self.progress.start.get_or_insert_with(Instant::now);
+        let mut sims_done = self.progress.sims_done;
+        self.suspended = false;
+        if self.args.forced_playouts {
+            self.forced_root = Some(s);
+        }
+        loop {
+            if self.proven_of(s, player) != Proven::Unknown {
+                break;
+            }
+            //ルートの子が一度も訪問されていないと方策が作れないので、それまでは続ける
+            let has_visits = self.root_counts(s).iter().any(|&c| c != 0);
+            let remaining = self.remaining_sims(limit, s, sims_done, start);
+            if has_visits {
+                if remaining == 0 {
+                    break;
                 }
-            })
-            .collect();
+                if self.args.early_stop && self.is_best_decided(s, remaining) {
+                    break;
+                }
+            }
+            self.search(unorthodox_board, player, turn);
+            if self.suspended {
+                self.progress.sims_done = sims_done;
+                self.forced_root = None;
+                return None;
+            }
+            sims_done += 1;
+            self.count_simulation();
+            if self.is_cancelled() {
+                self.progress.sims_done = sims_done;
+                self.forced_root = None;
+                return None;
+            }
+        }
+        self.forced_root = None;
+        self.progress = SearchProgress::default();
+
+        if let Some(a) = self.proven_win_action(s) {
+            let mut probs = vec![0.0; MOVE_LEN];
+            probs[a] = 1.0;
+            return Some(Pi::new(&probs));
+        }
+
+        //指し手は強制訪問も含めた訪問回数から選ぶ。取り除くのは教師データ(policy_target)だけ
+        let counts = self.root_counts(s);
 
         if temp == 0.0 {
             let count_max = *counts.iter().max().unwrap();
@@ -273,12 +645,11 @@ impl<'a> Mcts<'a> {
                 .map(|(index, _)| index)
                 .collect();
 
-            let mut rng = rand::thread_rng();
-            let index = rng.gen_range(0..best_as.len());
+            let index = self.rng.gen_range(0..best_as.len());
             let best_a = best_as[index];
             let mut probs = vec![0.0; counts.len()];
             probs[best_a] = 1.0;
-            return Pi::new(&probs);
+            return Some(Pi::new(&probs));
         } else {
             let counts: Vec<f32> = counts
                 .iter()
@@ -286,16 +657,16 @@ impl<'a> Mcts<'a> {
                 .collect();
             let counts_sum: f32 = counts.iter().sum();
             let probs: Vec<f32> = counts.iter().map(|&a| a / counts_sum).collect();
-            return Pi::new(&probs);
+            return Some(Pi::new(&probs));
         }
     }
 
-    /// returnするのはPLAYER1から見た勝敗の逆
+    /// returnするのはPLAYER1から見た勝敗の逆。
+    /// 評価器が評価を返さなければsuspendedを立て、木を変えずに戻る
     pub fn search(
         &mut self,
         unorthodox_board: &OthelloBoard,
         current_player: Player,
-        thinking_player: Player,
         turn: Turn,
     ) -> f32 {
         let canonical_board = unorthodox_board.create_canonical_board(current_player);
@@ -306,26 +677,22 @@ impl<'a> Mcts<'a> {
             game_end
         } else {
             //Canonical BoardのPlayer1から見た勝敗
-            let game_end = get_game_ended(&canonical_board, Player::PLAYER1);
+            let game_end = canonical_game_end(&canonical_board);
             self.is_game_end.insert(s, game_end);
             game_end
         };
+        let game_end = resolve_tie(game_end, current_player);
         if game_end != 0 {
             //Canonical BoardのPlayer1から見た勝敗はunorthodox boardでplayer2から見た勝敗と一致する
             //なので実際はcurrent_playerの情報はsearch関数では必要ない。元ソースにはないが、分かりやすくしたいのでいれている。
-            return -game_end as f32;
+            return -terminal_utility(&canonical_board, game_end, self.args.score_utility_weight);
         }
 
         let Some(node_info) = self.node.get_mut(&s) else {
-            self.send_to_main
-                .send(ThreadToMain::Board(
-                    canonical_board.clone(),
-                    self.thread_id.clone(),
-                    thinking_player,
-                    turn,
-                ))
-                .unwrap();
-            let MainToThread::Prediction(r) = self.receive_from_main.recv().unwrap();
+            let Some(r) = self.evaluator.try_evaluate(&canonical_board) else {
+                self.suspended = true;
+                return 0.0;
+            };
 
             let mut pi = r.action_probs;
 
@@ -348,27 +715,37 @@ impl<'a> Mcts<'a> {
                 }
             }
             //普通は最初のcountは1であろうが、元ソースでは0で動くようになっているので踏襲。
-            self.node.insert(s, NodeInfo::new(pi, 0, valid_moves, turn));
-            return -r.win_rate;
+            self.node
+                .insert(s, NodeInfo::new(pi, r.win_rate, 0, valid_moves, turn));
+            //スコアの効用をQに加える
+            return -combined_utility(
+                r.win_rate,
+                r.score.unwrap_or(0.0),
+                self.args.score_utility_weight,
+            );
         };
 
+        if node_info.proven != Proven::Unknown {
+            return -node_info.proven_value;
+        }
+
         let valids = &node_info.valid_moves;
         let mut cur_best = f32::NEG_INFINITY;
         let mut best_act = 0;
 
         for a in 0..MOVE_LEN {
             if valids[a] {
-                let u = if let Some(node_act) = self.node_act.get(&BoardState::new(s, a)) {
-                    let q = node_act.win_rate;
-                    q + self.args.cpuct
-                        * node_info.predicted_pi[a]
-                        * (node_info.count as f32).sqrt()
-                        / (1.0 + node_act.count as f32)
-                } else {
-                    self.args.cpuct
-                        * node_info.predicted_pi[a]
-                        * (node_info.count as f32 + EPS).sqrt()
-                };
+                let node_act = self.node_act.get(&BoardState::new(s, a));
+                if node_act.is_some_and(|info| info.proven == Proven::Loss) {
+                    continue;
+                }
+                let mut u = puct_score(self.args.cpuct, node_info, a, node_act);
+                if self.forced_root == Some(s) {
+                    let count = node_act.map_or(0, |info| info.count) as f32;
+                    if count < forced_visits(self.args.forced_playouts_k, node_info, a) {
+                        u = f32::INFINITY;
+                    }
+                }
 
                 if cur_best < u {
                     cur_best = u;
@@ -376,19 +753,132 @@ impl<'a> Mcts<'a> {
                 }
             }
         }
-        node_info.count += 1;
-
         let a = best_act;
         //boardはもう使わないので実際のところcloneしなくてもよいが、論理的にはcloneすべきだと思うのでcloneする
         let mut next_s = unorthodox_board.clone();
         get_next_state(&mut next_s, current_player, Action::new(a));
-        let v = self.search(
-            &next_s,
-            current_player.other(),
-            thinking_player,
-            turn.next(),
-        );
+        let v = self.search(&next_s, current_player.other(), turn.next());
+        if self.suspended {
+            return 0.0;
+        }
 
+        //中断したシミュレーションを数えないよう、訪問回数は戻るときに増やす
+        self.node.get_mut(&s).unwrap().count += 1;
+        self.update_node_act(s, a, v);
+        self.backup_proven(s, a, &next_s, current_player.other());
+
+        return -v;
+    }
+
+    /// ルートの各手のQを訪問回数で重み付けした平均。playerから見たもの
+    pub fn root_q(&self, unorthodox_board: &OthelloBoard, player: Player) -> f32 {
+        let canonical_board = unorthodox_board.create_canonical_board(player);
+        let s = canonical_board.string_representation();
+        if self.proven_of(s, player) != Proven::Unknown {
+            return self.proven_value(&canonical_board, s, player);
+        }
+        let mut sum = 0.0;
+        let mut count = 0;
+        for a in 0..MOVE_LEN {
+            if let Some(info) = self.node_act.get(&BoardState::new(s, a)) {
+                sum += info.win_rate * info.count as f32;
+                count += info.count;
+            }
+        }
+        if count == 0 {
+            self.node
+                .get(&s)
+                .map_or(0.0, |info| info.predicted_win_rate)
+        } else {
+            sum / count as f32
+        }
+    }
+
+    /// sの手番側から見た証明済みの勝敗。同点の終局はplayerによって変わる
+    pub fn proven_of(&self, s: u128, player: Player) -> Proven {
+        match self.is_game_end.get(&s).map(|&r| resolve_tie(r, player)) {
+            Some(1) => return Proven::Win,
+            Some(-1) => return Proven::Loss,
+            _ => {}
+        }
+        self.node
+            .get(&s)
+            .map_or(Proven::Unknown, |info| info.proven)
+    }
+
+    /// 証明済みのsについて、終局のスコアまで含めた手番側から見た効用
+    fn proven_value(&self, canonical_board: &OthelloBoard, s: u128, player: Player) -> f32 {
+        match self.is_game_end.get(&s).map(|&r| resolve_tie(r, player)) {
+            Some(r) if r != 0 => {
+                terminal_utility(canonical_board, r, self.args.score_utility_weight)
+            }
+            _ => self.node.get(&s).map_or(0.0, |info| info.proven_value),
+        }
+    }
+
+    /// 勝ちが証明された手のうち、効用が最も大きいもの
+    pub fn proven_win_action(&self, s: u128) -> Option<usize> {
+        (0..MOVE_LEN)
+            .filter_map(|a| {
+                self.node_act
+                    .get(&BoardState::new(s, a))
+                    .filter(|info| info.proven == Proven::Win)
+                    .map(|info| (a, info.proven_value))
+            })
+            .max_by(|x, y| x.1.total_cmp(&y.1))
+            .map(|(a, _)| a)
+    }
+
+    /// sでaを指した結果がnext_board(next_playerの手番)。子の勝敗が証明されていれば親に伝える。
+    /// 相手の負けが証明された手が一つでもあれば勝ち、すべての手で相手の勝ちが証明されていれば負け
+    pub(crate) fn backup_proven(
+        &mut self,
+        s: u128,
+        a: usize,
+        next_board: &OthelloBoard,
+        next_player: Player,
+    ) {
+        let next_canonical = next_board.create_canonical_board(next_player);
+        let next_s = next_canonical.string_representation();
+        let proven = self.proven_of(next_s, next_player).other();
+        if proven == Proven::Unknown {
+            return;
+        }
+        let value = -self.proven_value(&next_canonical, next_s, next_player);
+        if let Some(info) = self.node_act.get_mut(&BoardState::new(s, a)) {
+            info.proven = proven;
+            info.proven_value = value;
+        }
+
+        let valids = &self.node[&s].valid_moves;
+        let node_proven = if proven == Proven::Win {
+            Proven::Win
+        } else {
+            let all_loss = (0..MOVE_LEN).filter(|&a| valids[a]).all(|a| {
+                self.node_act
+                    .get(&BoardState::new(s, a))
+                    .is_some_and(|info| info.proven == Proven::Loss)
+            });
+            if all_loss {
+                Proven::Loss
+            } else {
+                return;
+            }
+        };
+        //勝ちなら勝つ手の中で、負けならすべての手の中で最も良い効用
+        let node_value = (0..MOVE_LEN)
+            .filter(|&a| valids[a])
+            .filter_map(|a| self.node_act.get(&BoardState::new(s, a)))
+            .filter(|info| info.proven == node_proven)
+            .map(|info| info.proven_value)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let node = self.node.get_mut(&s).unwrap();
+        node.proven = node_proven;
+        node.proven_value = node_value;
+    }
+
+    /// vはsの手番側から見た勝率
+    pub(crate) fn update_node_act(&mut self, s: u128, a: usize, v: f32) {
         let bs = BoardState::new(s, a);
         if let Some(node_act) = self.node_act.get_mut(&bs) {
             let n = &mut node_act.count;
@@ -399,8 +889,59 @@ impl<'a> Mcts<'a> {
         } else {
             self.node_act.insert(bs.clone(), NodeActionInfo::new(v, 1));
         }
+    }
+}
 
-        return -v;
+/// is_game_endで同点の終局を表す。canonical boardだけでは手番側の勝敗が決まらない
+const GAME_TIED: i32 = 2;
+
+/// canonical boardのPlayer1から見た勝敗。同点ならGAME_TIED
+pub(crate) fn canonical_game_end(canonical_board: &OthelloBoard) -> i32 {
+    let r = get_game_ended(canonical_board, Player::PLAYER1);
+    if r != 0 && canonical_board.count_diff(Player::PLAYER1) == 0 {
+        GAME_TIED
+    } else {
+        r
+    }
+}
+
+/// canonical_game_endの結果をplayerの手番から見た勝敗に直す。get_game_endedと同じく同点は先手の勝ち
+pub(crate) fn resolve_tie(game_end: i32, player: Player) -> i32 {
+    match game_end {
+        GAME_TIED if player == Player::PLAYER1 => 1,
+        GAME_TIED => -1,
+        r => r,
+    }
+}
+
+/// 終局したcanonical boardの手番側から見た効用。game_endはresolve_tieした勝敗
+pub(crate) fn terminal_utility(canonical_board: &OthelloBoard, game_end: i32, weight: f32) -> f32 {
+    let score = if weight == 0.0 {
+        0.0
+    } else {
+        canonical_board.count_diff(Player::PLAYER1) as f32 / (N * N) as f32
+    };
+    combined_utility(game_end as f32, score, weight)
+}
+
+/// 勝敗とスコアを重み付きで足した効用。1 + weightで割って[-1, 1]に収める
+pub(crate) fn combined_utility(value: f32, score: f32, weight: f32) -> f32 {
+    (value + weight * score) / (1.0 + weight)
+}
+
+/// 未訪問の手はQを0とする
+pub fn puct_score(
+    cpuct: f32,
+    node_info: &NodeInfo,
+    a: usize,
+    node_act: Option<&NodeActionInfo>,
+) -> f32 {
+    if let Some(node_act) = node_act {
+        let q = node_act.win_rate;
+        q + cpuct * node_info.predicted_pi[a] * (node_info.count as f32).sqrt()
+            / (1.0 + node_act.count as f32)
+    } else {
+        cpuct * node_info.predicted_pi[a] * (node_info.count as f32 + EPS).sqrt()
     }
 }
 
@@ -409,5 +950,6 @@ fn _predict_dummy() -> PredictResult {
     PredictResult {
         action_probs: Pi::new(&vec),
         win_rate: 0.1,
+        score: None,
     }
 }
@@ -1,8 +1,105 @@
-#[derive(Debug, Clone)]
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+/// ルートでの探索方法
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RootSearch {
+    /// PUCTで探索し、訪問回数から方策を作る
+    Puct,
+    /// Gumbel AlphaZero。Gumbel-Top-kで候補手を選び、Sequential Halvingで絞り込む。
+    /// 方策の教師データはCompleted Q-valueから作る
+    Gumbel,
+}
+
+/// 価値の教師データの作り方。zは最終結果、qはルートの探索Q
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueTarget {
+    Z,
+    Q,
+    /// (z + q) / 2
+    Average,
+    /// 手数で線形補間する。序盤ほどq、終盤ほどzに近づく
+    Interpolate,
+}
+
+impl ValueTarget {
+    /// plyは1から始まる手数、total_plyは試合全体の手数
+    pub fn mix(&self, z: f32, q: f32, ply: usize, total_ply: usize) -> f32 {
+        match self {
+            ValueTarget::Z => z,
+            ValueTarget::Q => q,
+            ValueTarget::Average => (z + q) / 2.0,
+            ValueTarget::Interpolate => {
+                let t = ply as f32 / total_ply.max(1) as f32;
+                t * z + (1.0 - t) * q
+            }
+        }
+    }
+}
+
+/// フルサーチ一手あたりの探索量。設定ファイルでは時間を秒で書く
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum SearchBudget {
+    /// num_mcts_sims回シミュレーションする
+    Simulations,
+    TimePerMove(#[serde(deserialize_with = "seconds")] Duration),
+    /// ルートの訪問回数。前の手から再利用した分も数える
+    RootVisits(usize),
+    /// 持ち時間と一手ごとの加算時間。残りの手数を見込んで一手に使う時間を決める
+    GameClock {
+        #[serde(deserialize_with = "seconds")]
+        main_time: Duration,
+        #[serde(deserialize_with = "seconds")]
+        increment: Duration,
+    },
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+/// 設定ファイルでは、フィールド名をそのままキーにする。書かなかったものはデフォルトの値になる
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct MctsArgs {
     pub temp_threshold: i32,
     pub num_mcts_sims: i32,
     pub cpuct: f32,
+    /// Playout cap randomization(KataGo)。この確率でnum_mcts_simsのフルサーチを行い、方策の教師データとする。
+    /// 1.0なら常にフルサーチ
+    pub full_search_prob: f32,
+    /// フルサーチでない手のシミュレーション回数。この手は指すが方策の教師データにはしない
+    pub fast_num_mcts_sims: i32,
+    pub root_search: RootSearch,
+    /// Gumbel-Top-kでサンプリングする候補手の数
+    pub gumbel_num_sampled_actions: usize,
+    pub gumbel_c_visit: f32,
+    pub gumbel_c_scale: f32,
+    pub value_target: ValueTarget,
+    pub search_budget: SearchBudget,
+    /// 最も訪問された手が残りの探索で逆転されなくなったら打ち切る
+    pub early_stop: bool,
+    /// ルートの探索Qがこれを下回ったら投了する。-1.0以下なら投了しない
+    pub resign_threshold: f32,
+    /// 投了を禁止する試合の割合
+    pub resign_disabled_fraction: f32,
+    /// 指し手のサンプリング、同点の手の選択、ノイズに使う乱数のseed。Noneなら再現性はない
+    pub seed: Option<u64>,
+    /// SelfPlayerの全スレッドで共有するNNの予測結果のキャッシュの大きさ。0ならキャッシュしない
+    pub eval_cache_size: usize,
+    /// Forced playouts(KataGo)。ルートの子を最低sqrt(forced_playouts_k * P * N)回訪問する。PUCTのみ
+    pub forced_playouts: bool,
+    pub forced_playouts_k: f32,
+    /// 方策の教師データから、PUCTなら選ばれなかった強制訪問を取り除く
+    pub policy_target_pruning: bool,
+    /// 予測した石差(スコア)にこの重みを掛けてQに加え、1 + 重みで割る。0ならスコアを使わない。
+    /// 投了の閾値もこの合成したQと比べる
+    pub score_utility_weight: f32,
 }
 
 impl Default for MctsArgs {
@@ -12,6 +109,83 @@ impl Default for MctsArgs {
             //temp_threshold: 100,
             num_mcts_sims: 25,
             cpuct: 1.0,
+            full_search_prob: 1.0,
+            fast_num_mcts_sims: 5,
+            root_search: RootSearch::Puct,
+            gumbel_num_sampled_actions: 16,
+            gumbel_c_visit: 50.0,
+            gumbel_c_scale: 1.0,
+            value_target: ValueTarget::Z,
+            search_budget: SearchBudget::Simulations,
+            early_stop: false,
+            resign_threshold: -1.0,
+            resign_disabled_fraction: 0.1,
+            seed: None,
+            eval_cache_size: 1 << 16,
+            forced_playouts: false,
+            forced_playouts_k: 2.0,
+            policy_target_pruning: false,
+            score_utility_weight: 0.0,
+        }
+    }
+}
+
+impl MctsArgs {
+    /// 範囲外の値があれば、そのフィールド名を含むメッセージを返す
+    pub fn validate(&self) -> Result<(), String> {
+        let check = |ok: bool, field: &str, condition: &str| {
+            if ok {
+                Ok(())
+            } else {
+                Err(format!("{field} must be {condition}"))
+            }
+        };
+        let probability = |p: f32| (0.0..=1.0).contains(&p);
+        check(0 <= self.temp_threshold, "temp_threshold", ">= 0")?;
+        check(0 < self.num_mcts_sims, "num_mcts_sims", "> 0")?;
+        check(0.0 < self.cpuct, "cpuct", "> 0")?;
+        check(
+            probability(self.full_search_prob),
+            "full_search_prob",
+            "in [0, 1]",
+        )?;
+        check(0 < self.fast_num_mcts_sims, "fast_num_mcts_sims", "> 0")?;
+        check(
+            0 < self.gumbel_num_sampled_actions,
+            "gumbel_num_sampled_actions",
+            "> 0",
+        )?;
+        check(0.0 <= self.gumbel_c_visit, "gumbel_c_visit", ">= 0")?;
+        check(0.0 < self.gumbel_c_scale, "gumbel_c_scale", "> 0")?;
+        match self.search_budget {
+            SearchBudget::TimePerMove(time) => {
+                check(!time.is_zero(), "search_budget.time_per_move", "> 0")?
+            }
+            SearchBudget::RootVisits(visits) => {
+                check(0 < visits, "search_budget.root_visits", "> 0")?
+            }
+            SearchBudget::GameClock {
+                main_time,
+                increment,
+            } => check(
+                !(main_time + increment).is_zero(),
+                "search_budget.game_clock",
+                "main_time + increment > 0",
+            )?,
+            SearchBudget::Simulations => {}
         }
+        check(self.resign_threshold <= 1.0, "resign_threshold", "<= 1")?;
+        check(
+            probability(self.resign_disabled_fraction),
+            "resign_disabled_fraction",
+            "in [0, 1]",
+        )?;
+        check(0.0 < self.forced_playouts_k, "forced_playouts_k", "> 0")?;
+        check(
+            0.0 <= self.score_utility_weight,
+            "score_utility_weight",
+            ">= 0",
+        )?;
+        Ok(())
     }
 }
@@ -1,32 +1,74 @@
 use crate::{action::{Pi, ValidMoves}, mcts::Turn};
 
+/// MCTS-solverで証明された勝敗。手番側から見たもの
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Proven {
+    Unknown,
+    Win,
+    Loss,
+}
+
+impl Proven {
+    /// 相手から見た勝敗
+    pub fn other(&self) -> Proven {
+        match self {
+            Proven::Unknown => Proven::Unknown,
+            Proven::Win => Proven::Loss,
+            Proven::Loss => Proven::Win,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct NodeActionInfo {
     pub win_rate: f32,
     pub count: usize,
+    /// この手を指した場合の勝敗
+    pub proven: Proven,
+    /// 証明済みなら、その終局のスコアまで含めた効用
+    pub proven_value: f32,
 }
 
 impl NodeActionInfo {
     pub fn new(win_rate: f32, count: usize) -> Self {
-        Self { win_rate, count }
+        Self {
+            win_rate,
+            count,
+            proven: Proven::Unknown,
+            proven_value: 0.0,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct NodeInfo {
     pub predicted_pi: Pi,
+    /// NNが予測したこの局面の手番側から見た勝率
+    pub predicted_win_rate: f32,
     pub count: usize,
     pub valid_moves: ValidMoves,
 	pub _turn: Turn,
+    pub proven: Proven,
+    /// 証明済みなら、その終局のスコアまで含めた手番側から見た効用
+    pub proven_value: f32,
 }
 
 impl NodeInfo {
-    pub fn new(predicted_pi: Pi, count: usize, valid_moves: ValidMoves, turn: Turn) -> Self {
+    pub fn new(
+        predicted_pi: Pi,
+        predicted_win_rate: f32,
+        count: usize,
+        valid_moves: ValidMoves,
+        turn: Turn,
+    ) -> Self {
         Self {
             predicted_pi,
+            predicted_win_rate,
             count,
             valid_moves,
-			_turn: turn
+			_turn: turn,
+            proven: Proven::Unknown,
+            proven_value: 0.0,
         }
     }
 }
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use crate::alpha_beta::AlphaBeta;
+use crate::evaluator::RolloutEvaluator;
+use crate::mcts::MctsContext;
+use crate::mcts_args::MctsArgs;
+use crate::player::Player;
+
+/// 事前確率が一様なので、PUCTの探索項が古典的なUCTと同程度になるように大きめにする
+const UCT_CPUCT: f32 = 4.0;
+
+/// 2Playerモードで、NNの代わりに片方の手番を受け持つ相手
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Opponent {
+    /// ランダムプレイアウトで評価し、一様な事前確率で探索するUCT。
+    /// num_rolloutsは一つの葉で行うプレイアウトの回数。探索の設定は学習側のものを引き継がない
+    Uct {
+        num_sims: usize,
+        num_rollouts: usize,
+    },
+    /// 手作りの評価関数によるアルファベータ探索。depthとtimeの少なくとも片方を指定する
+    AlphaBeta {
+        depth: Option<usize>,
+        time: Option<Duration>,
+    },
+}
+
+impl Opponent {
+    /// mctsのplayerの手番を、この相手が指すようにする
+    pub fn install(&self, mcts: &mut MctsContext, player: Player) {
+        match *self {
+            Opponent::Uct {
+                num_sims,
+                num_rollouts,
+            } => {
+                //MctsContextのrngと別の乱数列にする
+                let rng = mcts.thread_id.create_rng(mcts.args.seed.map(|seed| !seed));
+                let evaluator = Box::new(RolloutEvaluator::new(num_rollouts, rng));
+                if player == Player::PLAYER1 {
+                    mcts.p1_evaluator = evaluator;
+                } else {
+                    mcts.p2_evaluator = Some(evaluator);
+                }
+                //学習側の設定が変わっても基準が変わらないよう、デフォルトから作る
+                let uct_args = MctsArgs {
+                    num_mcts_sims: num_sims as i32,
+                    cpuct: UCT_CPUCT,
+                    ..MctsArgs::default()
+                };
+                mcts.set_player_args(player, uct_args);
+            }
+            Opponent::AlphaBeta { depth, time } => {
+                mcts.set_player_alpha_beta(player, AlphaBeta::new(depth, time));
+            }
+        }
+    }
+}
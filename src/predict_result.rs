@@ -1,16 +1,21 @@
 use crate::{action::Pi, c_array::CArray};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PredictResult {
     pub win_rate: f32,
     pub action_probs: Pi,
+    /// 予測した最終的な石差。手番側から見て、マスの数で割って-1..1にしたもの。スコアヘッドがなければNone
+    pub score: Option<f32>,
 }
 
 impl PredictResult{
-	pub fn convert_from_carrays(pis : &CArray<f32>, winrates: &CArray<f32>) -> Vec<PredictResult>{
+	pub fn convert_from_carrays(pis : &CArray<f32>, winrates: &CArray<f32>, scores: Option<&CArray<f32>>) -> Vec<PredictResult>{
 		if winrates.size0() != pis.size0(){
 			panic!("winrates and pis sizes must be the same");
 		}
+		if scores.is_some_and(|scores| scores.size0() != pis.size0()){
+			panic!("scores and pis sizes must be the same");
+		}
 
 		let len = winrates.size0();
 
@@ -19,7 +24,8 @@ impl PredictResult{
 		for i in 0..winrates.size0(){
 			vec.push(PredictResult{
 				action_probs: Pi::new(pis.ref2(i)),
-				win_rate: winrates.get2(i, 0)
+				win_rate: winrates.get2(i, 0),
+				score: scores.map(|scores| scores.get2(i, 0)),
 			});
 		}
 
@@ -1,22 +1,33 @@
 
 use crate::{
     constant::{BATCH_SIZE, BOARD_SIZE, MOVE_LEN, N},
-    mcts_args::MctsArgs,
+    engine_config::EngineConfig,
+    mcts_args::{MctsArgs, RootSearch, SearchBudget, ValueTarget},
+    self_player::{write_c_error, SelfPlayConfig},
 };
 
+use std::ffi::{c_char, CStr};
+use std::time::Duration;
+
 use threadpool::ThreadPool;
 
 pub struct PyCommunicator {
     pub pool: ThreadPool,
     pub mcts_args: MctsArgs,
+    pub self_play: SelfPlayConfig,
 }
 
 impl PyCommunicator {
     pub fn new() -> Self {
-        let mcts_args = MctsArgs::default();
+        Self::from_config(EngineConfig::default())
+    }
+
+    pub fn from_config(config: EngineConfig) -> Self {
+        //試合は評価待ちで中断するタスクとして各スレッドに振り分けるので、CPUの数だけあればよい
         Self {
-            pool: ThreadPool::new(BATCH_SIZE),
-            mcts_args,
+            pool: ThreadPool::new(config.num_threads()),
+            self_play: config.self_play(),
+            mcts_args: config.mcts,
         }
     }
 }
@@ -27,6 +38,27 @@ pub extern "C" fn create_py_communicator() -> *mut PyCommunicator {
     Box::into_raw(b)
 }
 
+/// configはJSONかTOMLの文字列(EngineConfig)。
+/// 読めないか値が不正ならNULL POINTER(0)を返し、errorがNULL POINTERでなければメッセージを書く。
+/// メッセージはdestroy_c_stringで解放する
+#[no_mangle]
+pub extern "C" fn create_py_communicator_with_config(
+    config: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut PyCommunicator {
+    let parsed = unsafe { CStr::from_ptr(config) }
+        .to_str()
+        .map_err(|e| e.to_string())
+        .and_then(EngineConfig::parse);
+    match parsed {
+        Ok(config) => Box::into_raw(Box::new(PyCommunicator::from_config(config))),
+        Err(message) => {
+            write_c_error(error, &message);
+            std::ptr::null_mut()
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn destroy_py_communicator(p: *mut PyCommunicator) {
     unsafe {
@@ -34,6 +66,215 @@ pub extern "C" fn destroy_py_communicator(p: *mut PyCommunicator) {
     }
 }
 
+/// root_search: 0ならPUCT、1ならGumbel。それ以外なら何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_root_search(
+    p: *mut PyCommunicator,
+    root_search: usize,
+) -> bool {
+    let root_search = match root_search {
+        0 => RootSearch::Puct,
+        1 => RootSearch::Gumbel,
+        _ => return false,
+    };
+    unsafe {
+        (*p).mcts_args.root_search = root_search;
+    }
+    true
+}
+
+/// value_target: 0ならz、1ならq、2なら(z+q)/2、3なら手数で線形補間。それ以外なら何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_value_target(
+    p: *mut PyCommunicator,
+    value_target: usize,
+) -> bool {
+    let value_target = match value_target {
+        0 => ValueTarget::Z,
+        1 => ValueTarget::Q,
+        2 => ValueTarget::Average,
+        3 => ValueTarget::Interpolate,
+        _ => return false,
+    };
+    unsafe {
+        (*p).mcts_args.value_target = value_target;
+    }
+    true
+}
+
+/// kind: 0ならnum_mcts_sims回、1なら一手value秒、2ならルートの訪問回数value回、
+/// 3なら持ち時間value秒で一手ごとにincrement秒加算。
+/// kindが不明か、時間が負・非有限、回数が非負の整数でなければ何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_search_budget(
+    p: *mut PyCommunicator,
+    kind: usize,
+    value: f64,
+    increment: f64,
+) -> bool {
+    let secs = |x: f64| Duration::try_from_secs_f64(x).ok();
+    let budget = match kind {
+        0 => Some(SearchBudget::Simulations),
+        1 => secs(value).map(SearchBudget::TimePerMove),
+        2 if 0.0 <= value && value.fract() == 0.0 && value < usize::MAX as f64 => {
+            Some(SearchBudget::RootVisits(value as usize))
+        }
+        3 => secs(value)
+            .zip(secs(increment))
+            .map(|(main_time, increment)| SearchBudget::GameClock {
+                main_time,
+                increment,
+            }),
+        _ => None,
+    };
+    let Some(budget) = budget else {
+        return false;
+    };
+    unsafe {
+        (*p).mcts_args.search_budget = budget;
+    }
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn py_communicator_set_early_stop(p: *mut PyCommunicator, early_stop: bool) {
+    unsafe {
+        (*p).mcts_args.early_stop = early_stop;
+    }
+}
+
+/// forced_playouts: ルートの子を最低sqrt(k * P * N)回訪問する(PUCTのみ)
+/// policy_target_pruning: 方策の教師データから強制された訪問を取り除く。
+/// kが正でなければ何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_forced_playouts(
+    p: *mut PyCommunicator,
+    forced_playouts: bool,
+    k: f32,
+    policy_target_pruning: bool,
+) -> bool {
+    unsafe {
+        set_mcts_args(
+            p,
+            MctsArgs {
+                forced_playouts,
+                forced_playouts_k: k,
+                policy_target_pruning,
+                ..(*p).mcts_args.clone()
+            },
+        )
+    }
+}
+
+/// 予測した石差にweightを掛けてQに加え、1 + weightで割る。0ならスコアを使わない。
+/// weightが負かNaNなら何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_score_utility_weight(
+    p: *mut PyCommunicator,
+    weight: f32,
+) -> bool {
+    unsafe {
+        set_mcts_args(
+            p,
+            MctsArgs {
+                score_utility_weight: weight,
+                ..(*p).mcts_args.clone()
+            },
+        )
+    }
+}
+
+/// 同時に進める試合の数、NNに一度に渡す盤面の最大数、一つのSelfPlayerで行う試合の数。
+/// どれかが0なら何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_self_play(
+    p: *mut PyCommunicator,
+    concurrent_games: usize,
+    batch_size: usize,
+    games_per_generation: usize,
+) -> bool {
+    unsafe {
+        set_self_play(
+            p,
+            SelfPlayConfig {
+                concurrent_games,
+                batch_size,
+                games_per_generation,
+                ..(*p).self_play
+            },
+        )
+    }
+}
+
+/// min_batch_size個の盤面が揃うか、timeout_usマイクロ秒待ったらバッチを返す。
+/// min_batch_sizeが0なら何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_batch_dispatch(
+    p: *mut PyCommunicator,
+    min_batch_size: usize,
+    timeout_us: u64,
+) -> bool {
+    unsafe {
+        set_self_play(
+            p,
+            SelfPlayConfig {
+                min_batch_size,
+                batch_timeout: Duration::from_micros(timeout_us),
+                ..(*p).self_play
+            },
+        )
+    }
+}
+
+fn set_self_play(p: *mut PyCommunicator, self_play: SelfPlayConfig) -> bool {
+    if self_play.validate().is_err() {
+        return false;
+    }
+    unsafe {
+        (*p).self_play = self_play;
+    }
+    true
+}
+
+/// threshold: ルートの探索Qがこれを下回ったら投了する。-1.0以下なら投了しない
+/// disabled_fraction: 投了を禁止する試合の割合。
+/// thresholdが1より大きいかNaN、disabled_fractionが[0, 1]になければ何もせずfalseを返す
+#[no_mangle]
+pub extern "C" fn py_communicator_set_resign(
+    p: *mut PyCommunicator,
+    threshold: f32,
+    disabled_fraction: f32,
+) -> bool {
+    unsafe {
+        set_mcts_args(
+            p,
+            MctsArgs {
+                resign_threshold: threshold,
+                resign_disabled_fraction: disabled_fraction,
+                ..(*p).mcts_args.clone()
+            },
+        )
+    }
+}
+
+fn set_mcts_args(p: *mut PyCommunicator, mcts_args: MctsArgs) -> bool {
+    if mcts_args.validate().is_err() {
+        return false;
+    }
+    unsafe {
+        (*p).mcts_args = mcts_args;
+    }
+    true
+}
+
+/// SelfPlayerを作るたびにseedは1ずつ進むので、学習全体を通して再現できる
+#[no_mangle]
+pub extern "C" fn py_communicator_set_seed(p: *mut PyCommunicator, seed: u64) {
+    unsafe {
+        (*p).mcts_args.seed = Some(seed);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn batch_size() -> usize {
     BATCH_SIZE
@@ -0,0 +1,71 @@
+use std::collections::VecDeque;
+
+use rand::{rngs::StdRng, seq::index};
+
+use crate::{c_array::CArray, example_batch::ExampleBatch, mcts::TrainExample};
+
+/// 終わった試合の教師データを溜めておく。自己対戦を止めずに、ここから学習のミニバッチを取り出す。
+/// capacityを超えたら古いものから捨てる
+pub struct ReplayBuffer {
+    capacity: usize,
+    examples: VecDeque<TrainExample>,
+    rng: StdRng,
+    /// これまでに入れた試合の数
+    games: usize,
+    /// これまでに入れた教師データの数。捨てたものも含む
+    total_examples: usize,
+    /// 直前にsampleで取り出したもの
+    sample: ExampleBatch,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize, rng: StdRng) -> Self {
+        Self {
+            capacity,
+            examples: VecDeque::with_capacity(capacity),
+            rng,
+            games: 0,
+            total_examples: 0,
+            sample: ExampleBatch::default(),
+        }
+    }
+
+    /// フルサーチした手だけを入れる(playout cap randomization)
+    pub fn push_game(&mut self, examples: Vec<TrainExample>) {
+        self.games += 1;
+        for example in examples.into_iter().filter(|e| e.is_full_search) {
+            if self.examples.len() == self.capacity {
+                self.examples.pop_front();
+            }
+            self.examples.push_back(example);
+            self.total_examples += 1;
+        }
+    }
+
+    /// 重複なしでnum個(溜まっていなければ全部)取り出し、取り出した数を返す。結果はsampledで得る
+    pub fn sample(&mut self, num: usize) -> usize {
+        let amount = num.min(self.examples.len());
+        self.sample = ExampleBatch::new(
+            index::sample(&mut self.rng, self.examples.len(), amount)
+                .into_iter()
+                .map(|i| self.examples[i].clone())
+                .collect(),
+        );
+        amount
+    }
+
+    pub fn sampled(&self) -> &ExampleBatch {
+        &self.sample
+    }
+
+    /// [溜まっている教師データの数, これまでに入れた試合の数, これまでに入れた教師データの数]
+    pub fn get_stats(&self) -> CArray<f32> {
+        let mut array = CArray::<f32>::new1(3);
+        array.as_mut().copy_from_slice(&[
+            self.examples.len() as f32,
+            self.games as f32,
+            self.total_examples as f32,
+        ]);
+        array
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::{Duration, Instant};
+
+use crate::constant::MOVE_LEN;
+use crate::evaluator::Evaluator;
+use crate::mcts::{BoardState, Mcts};
+use crate::mcts_args::{MctsArgs, SearchBudget};
+use crate::othello_board::OthelloBoard;
+use crate::player::Player;
+
+/// 一手分の探索の上限
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchLimit {
+    Simulations(usize),
+    /// ルートの訪問回数。前の手から再利用した分も数える
+    RootVisits(usize),
+    Deadline(Instant),
+}
+
+/// MctsArgs::search_budgetがGameClockの場合の各プレイヤーの残り時間
+#[derive(Debug, Clone)]
+pub struct GameClock {
+    remaining: [Duration; 2],
+    increment: Duration,
+}
+
+impl GameClock {
+    pub fn new(main_time: Duration, increment: Duration) -> Self {
+        Self {
+            remaining: [main_time; 2],
+            increment,
+        }
+    }
+
+    fn index(player: Player) -> usize {
+        if player == Player::PLAYER1 {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// 残り時間を、自分の残りの手数の見込み(空きマスの半分)で割って割り当てる
+    pub fn allot(&self, board: &OthelloBoard, player: Player) -> Duration {
+        let empty = board.0.as_flattened().iter().filter(|&&c| c == 0).count();
+        let moves_left = (empty / 2).max(1) as u32;
+        self.remaining[Self::index(player)] / moves_left + self.increment
+    }
+
+    /// 時間切れ負けはない。残り時間が0になったらincrementだけで指す
+    pub fn consume(&mut self, player: Player, elapsed: Duration) {
+        let r = &mut self.remaining[Self::index(player)];
+        *r = r.saturating_sub(elapsed) + self.increment;
+    }
+}
+
+impl SearchLimit {
+    /// フルサーチの場合の上限
+    pub fn from_args(
+        args: &MctsArgs,
+        clock: Option<&GameClock>,
+        board: &OthelloBoard,
+        player: Player,
+    ) -> Self {
+        match args.search_budget {
+            SearchBudget::Simulations => {
+                SearchLimit::Simulations(args.num_mcts_sims.max(0) as usize)
+            }
+            SearchBudget::TimePerMove(d) => SearchLimit::Deadline(Instant::now() + d),
+            SearchBudget::RootVisits(n) => SearchLimit::RootVisits(n),
+            SearchBudget::GameClock { .. } => {
+                let allotted = clock
+                    .expect("GameClock is not prepared")
+                    .allot(board, player);
+                SearchLimit::Deadline(Instant::now() + allotted)
+            }
+        }
+    }
+}
+
+impl<'a, E: Evaluator + ?Sized> Mcts<'a, E> {
+    pub fn root_counts(&self, s: u128) -> Vec<usize> {
+        (0..MOVE_LEN)
+            .map(|a| {
+                if let Some(info) = self.node_act.get(&BoardState::new(s, a)) {
+                    info.count
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    /// あと何回シミュレーションできるか。時間制限の場合はこれまでの速度から見積もる
+    pub(crate) fn remaining_sims(
+        &self,
+        limit: SearchLimit,
+        s: u128,
+        sims_done: usize,
+        start: Instant,
+    ) -> usize {
+        match limit {
+            SearchLimit::Simulations(n) => n.saturating_sub(sims_done),
+            SearchLimit::RootVisits(n) => {
+                n.saturating_sub(self.root_counts(s).iter().sum::<usize>())
+            }
+            SearchLimit::Deadline(deadline) => {
+                let now = Instant::now();
+                if deadline <= now {
+                    0
+                } else if sims_done == 0 {
+                    usize::MAX
+                } else {
+                    let elapsed = now.duration_since(start).as_secs_f64();
+                    let left = deadline.duration_since(now).as_secs_f64();
+                    ((sims_done as f64 * left / elapsed.max(1e-9)) as usize).max(1)
+                }
+            }
+        }
+    }
+
+    /// 残りのシミュレーションを全部2番目の手に使っても、最も訪問された手を追い越せないか
+    pub(crate) fn is_best_decided(&self, s: u128, remaining: usize) -> bool {
+        let mut counts = self.root_counts(s);
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        counts[1].saturating_add(remaining) < counts[0]
+    }
+
+    /// Gumbelのsequential halvingは最初に総シミュレーション回数が必要なので、回数に直す。
+    /// 時間制限の場合はnum_mcts_simsを使い、期限を過ぎたらそこで打ち切る
+    pub(crate) fn limit_to_sims(&self, limit: SearchLimit, s: u128) -> usize {
+        match limit {
+            SearchLimit::Simulations(n) => n,
+            SearchLimit::RootVisits(_) => self.remaining_sims(limit, s, 0, Instant::now()),
+            SearchLimit::Deadline(_) => self.args.num_mcts_sims.max(0) as usize,
+        }
+    }
+}
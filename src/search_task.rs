@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::analysis::AnalysisSlots;
+use crate::evaluator::{PendingEval, SuspendingEvaluator};
+use crate::mcts::{Episode, MctsContext, ResignRecord};
+use crate::othello_board::OthelloBoard;
+use crate::player::Player;
+use crate::predict_result::PredictResult;
+use crate::shared_tree::{PendingSimulation, SharedTree};
+use crate::thread_id::ThreadID;
+
+pub enum TaskState {
+    /// canonical boardの評価を思考担当のNNで待っている
+    Waiting(OthelloBoard, Player),
+    Finished(Episode),
+    /// 中止された。途中までの結果は捨てる
+    Cancelled,
+}
+
+/// 評価待ちで中断できる仕事。少ないワーカースレッドで多数のタスクを交互に進める
+pub trait SearchTask {
+    /// 評価待ちになるか終わるまで進める
+    fn resume(&mut self) -> TaskState;
+    /// Waitingで返した盤面の評価を渡す
+    fn receive(&mut self, prediction: PredictResult);
+}
+
+pub type BoxedTask = Box<dyn SearchTask + Send>;
+
+/// 自己対戦の一試合。MctsContextの評価器はpendingを共有するSuspendingEvaluatorにしておく
+pub struct GameTask {
+    context: MctsContext,
+    pending: Arc<Mutex<PendingEval>>,
+}
+
+impl GameTask {
+    pub fn new(context: MctsContext, pending: Arc<Mutex<PendingEval>>) -> Self {
+        Self { context, pending }
+    }
+}
+
+impl SearchTask for GameTask {
+    fn resume(&mut self) -> TaskState {
+        match self.context.resume_episode() {
+            Some(episode) => TaskState::Finished(episode),
+            None if self.context.is_cancelled() => TaskState::Cancelled,
+            None => {
+                let (board, player) = self.pending.lock().unwrap().request().unwrap();
+                TaskState::Waiting(board, player)
+            }
+        }
+    }
+
+    fn receive(&mut self, prediction: PredictResult) {
+        self.pending.lock().unwrap().respond(prediction);
+    }
+}
+
+/// SharedTreeを共有する探索。全レーンで共有する
+pub struct SharedSearch {
+    pub tree: SharedTree,
+    pub board: OthelloBoard,
+    pub player: Player,
+    pub num_sims: usize,
+    pub num_lanes: usize,
+    /// 全レーンで開始したシミュレーションの数
+    pub started: AtomicUsize,
+    /// 終わったレーンの数
+    pub finished: AtomicUsize,
+}
+
+/// SharedSearchの一つのレーン。レーンごとに一つのシミュレーションが評価を待てる
+pub struct SharedSearchTask {
+    search: Arc<SharedSearch>,
+    evaluator: SuspendingEvaluator,
+    pending: Arc<Mutex<PendingEval>>,
+    simulation: Option<PendingSimulation>,
+    analysis_slots: AnalysisSlots,
+    cancel: Arc<AtomicBool>,
+    /// 終わったシミュレーションの数。全スロットで共有する
+    simulations: Arc<AtomicUsize>,
+}
+
+impl SharedSearchTask {
+    pub fn new(
+        search: Arc<SharedSearch>,
+        evaluator: SuspendingEvaluator,
+        pending: Arc<Mutex<PendingEval>>,
+        analysis_slots: AnalysisSlots,
+        cancel: Arc<AtomicBool>,
+        simulations: Arc<AtomicUsize>,
+    ) -> Self {
+        Self {
+            search,
+            evaluator,
+            pending,
+            simulation: None,
+            analysis_slots,
+            cancel,
+            simulations,
+        }
+    }
+}
+
+impl SearchTask for SharedSearchTask {
+    fn resume(&mut self) -> TaskState {
+        let search = &self.search;
+        loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                return TaskState::Cancelled;
+            }
+            self.simulation = match self.simulation.take() {
+                Some(simulation) => search
+                    .tree
+                    .resume_simulation(simulation, &mut self.evaluator),
+                None => {
+                    if search.num_sims <= search.started.fetch_add(1, Ordering::AcqRel) {
+                        break;
+                    }
+                    search
+                        .tree
+                        .try_simulate(&search.board, search.player, &mut self.evaluator)
+                }
+            };
+            if self.simulation.is_some() {
+                let (board, player) = self.pending.lock().unwrap().request().unwrap();
+                return TaskState::Waiting(board, player);
+            }
+            self.simulations.fetch_add(1, Ordering::Relaxed);
+        }
+        //最後に終わったレーンが結果を書く。送信より前なので、prepare_nextが2を返した時には書かれている
+        if search.finished.fetch_add(1, Ordering::AcqRel) + 1 == search.num_lanes {
+            self.analysis_slots.set(
+                &ThreadID::new(0),
+                search.tree.analyze(&search.board, search.player),
+            );
+        }
+        TaskState::Finished(Episode {
+            examples: vec![],
+            resign: ResignRecord::NotResigned,
+        })
+    }
+
+    fn receive(&mut self, prediction: PredictResult) {
+        self.pending.lock().unwrap().respond(prediction);
+    }
+}
@@ -1,75 +1,480 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::ffi::{c_char, CStr, CString};
+use std::io;
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
 
 use threadpool::ThreadPool;
 
 use crate::{
+    analysis::AnalysisSlots,
     c_array::CArray,
     constant::{BATCH_SIZE, MOVE_LEN, N},
-    mcts::{MainToThread, MctsContext, PlayerMode, ThreadToMain, TrainExample},
+    dedup::dedup,
+    eval_cache::EvalCache,
+    example_batch::ExampleBatch,
+    example_file::ExampleWriter,
+    evaluator::{BoxedEvaluator, PendingEval, SuspendingEvaluator},
+    mcts::{MainToThread, MctsContext, PlayerMode, ResignRecord, ThreadToMain, TrainExample},
     mcts_args::MctsArgs,
+    opponent::Opponent,
     othello_board::OthelloBoard,
     player::Player,
     predict_result::PredictResult,
     py_communicator::PyCommunicator,
+    replay_buffer::ReplayBuffer,
+    search_task::{BoxedTask, GameTask, SharedSearch, SharedSearchTask, TaskState},
+    shared_tree::SharedTree,
     thread_id::ThreadID,
+    tree_export::TreeExportSlots,
 };
 
 pub struct ThreadInfo {
     pub send_to_thread: Sender<MainToThread>,
-    pub receive_from_thread: Receiver<ThreadToMain>,
     pub data: Option<ThreadToMain>,
+    /// このスロットではもう試合を始めない
+    pub closed: bool,
+    /// 試合を進めている。試合が終わると下ろし、次の試合の盤面が届くと立てる
+    pub playing: bool,
+}
+
+/// 同時に進める試合の数、NNに一度に渡す盤面の数、一つのSelfPlayerで行う試合の数。
+/// prepare_nextはmin_batch_size個(batch_sizeより大きければbatch_size個)の盤面が揃うか、
+/// batch_timeoutが過ぎたら揃った分だけでバッチを返す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfPlayConfig {
+    pub concurrent_games: usize,
+    pub batch_size: usize,
+    pub games_per_generation: usize,
+    pub min_batch_size: usize,
+    pub batch_timeout: Duration,
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            concurrent_games: BATCH_SIZE,
+            batch_size: BATCH_SIZE,
+            games_per_generation: BATCH_SIZE,
+            min_batch_size: BATCH_SIZE,
+            batch_timeout: Duration::from_millis(1),
+        }
+    }
+}
+
+impl SelfPlayConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.concurrent_games == 0 {
+            return Err("concurrent_games must be > 0".to_string());
+        }
+        if self.batch_size == 0 {
+            return Err("batch_size must be > 0".to_string());
+        }
+        if self.games_per_generation == 0 {
+            return Err("games_per_generation must be > 0".to_string());
+        }
+        if self.min_batch_size == 0 {
+            return Err("min_batch_size must be > 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// 試合を進めるスロットの数。games_per_generationより多く作っても使われない
+    fn num_slots(&self) -> usize {
+        self.concurrent_games.min(self.games_per_generation)
+    }
+}
+
+/// タスクを作るときに使う共有物
+struct TaskEnv {
+    thread_id: ThreadID,
+    mcts_args: MctsArgs,
+    analysis_slots: AnalysisSlots,
+    tree_exports: TreeExportSlots,
+    eval_cache: Arc<EvalCache>,
+    /// SelfPlayer::cancelでtrueになる
+    cancel: Arc<AtomicBool>,
+    /// 全スロットで終わったシミュレーションの数
+    simulations: Arc<AtomicUsize>,
+}
+
+impl TaskEnv {
+    fn evaluator(
+        &self,
+        pending: &Arc<Mutex<PendingEval>>,
+        thinking_player: Player,
+    ) -> SuspendingEvaluator {
+        SuspendingEvaluator::new(pending.clone(), thinking_player, self.eval_cache.clone())
+    }
+}
+
+type TaskFactory = dyn Fn(&TaskEnv) -> BoxedTask + Send + Sync;
+
+/// 全ワーカーで共有する、まだ始めていない試合の数
+struct GameQuota {
+    started: AtomicUsize,
+    total: usize,
+}
+
+impl GameQuota {
+    /// 次の試合を始めてよいならtrue
+    fn take(&self) -> bool {
+        self.started.fetch_add(1, Ordering::AcqRel) < self.total
+    }
+}
+
+/// ワーカースレッドが受け持つタスクと、メインへの送信手段
+struct TaskSlot {
+    env: TaskEnv,
+    send_to_main: Sender<ThreadToMain>,
+    task: BoxedTask,
+}
+
+impl TaskSlot {
+    /// 評価待ちなら盤面を、終わったらTrainExamplesをメインに送る。
+    /// 終わったスロットではquotaが残っていれば次の試合を始める。スロットを閉じるならtrue。
+    /// 中止されたときと、SelfPlayerが捨てられて送れないときも閉じる
+    fn resume(&mut self, create_task: &TaskFactory, quota: &GameQuota) -> bool {
+        loop {
+            match self.task.resume() {
+                TaskState::Waiting(board, player) => {
+                    let message = ThreadToMain::Board(board, self.env.thread_id.clone(), player);
+                    return self.send_to_main.send(message).is_err();
+                }
+                TaskState::Finished(episode) => {
+                    let message = ThreadToMain::TrainExamples(episode, self.env.thread_id.clone());
+                    if self.send_to_main.send(message).is_err() {
+                        return true;
+                    }
+                    if !quota.take() {
+                        //メインがもういなければ送れなくてもよい
+                        let _ = self
+                            .send_to_main
+                            .send(ThreadToMain::Closed(self.env.thread_id.clone()));
+                        return true;
+                    }
+                    //同じスロットでも試合ごとに別の乱数列にする。SelfPlayerごとのseedは1ずつしか進まないので上位に足す
+                    if let Some(seed) = &mut self.env.mcts_args.seed {
+                        *seed = seed.wrapping_add(1 << 32);
+                    }
+                    self.task = create_task(&self.env);
+                }
+                TaskState::Cancelled => return true,
+            }
+        }
+    }
+}
+
+/// 一つのワーカースレッドで複数のタスクを進める。予測が届いたタスクだけを再開する。
+/// slotsのi番目のthread_idはi * num_workers + (ワーカーの番号)。
+/// 閉じたスロットはNoneにする。SelfPlayerが捨てられて予測が届かなくなったら終わる
+fn run_worker(
+    slots: Vec<TaskSlot>,
+    receiver: Receiver<MainToThread>,
+    num_workers: usize,
+    create_task: Arc<TaskFactory>,
+    quota: Arc<GameQuota>,
+) {
+    let mut slots: Vec<Option<TaskSlot>> = slots.into_iter().map(Some).collect();
+    let mut running = slots.len();
+    for slot in &mut slots {
+        if slot.as_mut().unwrap().resume(&*create_task, &quota) {
+            *slot = None;
+            running -= 1;
+        }
+    }
+    while 0 < running {
+        let Ok(MainToThread::Prediction(prediction, thread_id)) = receiver.recv() else {
+            return;
+        };
+        let slot = &mut slots[thread_id.id() / num_workers];
+        let task_slot = slot.as_mut().unwrap();
+        task_slot.task.receive(prediction);
+        if task_slot.resume(&*create_task, &quota) {
+            *slot = None;
+            running -= 1;
+        }
+    }
+}
+
+/// 自己対戦の進み具合。get_statsで返す
+struct PlayStats {
+    start: Instant,
+    /// 受け取った試合の数と、その手数の合計
+    games: usize,
+    plies: usize,
+    /// Pythonで評価した盤面の数と、そのバッチの数
+    positions: usize,
+    batches: usize,
+    simulations: Arc<AtomicUsize>,
 }
 
 pub struct SelfPlayer {
+    config: SelfPlayConfig,
     thread_infos: Vec<ThreadInfo>,
+    /// 全スロットからの盤面、TrainExamples、Closed
+    receive_from_threads: Receiver<ThreadToMain>,
+    analysis_slots: AnalysisSlots,
+    tree_exports: TreeExportSlots,
+    eval_cache: Arc<EvalCache>,
+    /// 終わった試合。すべて終わったらtrain_examplesに移す
+    finished_games: Vec<Vec<TrainExample>>,
+    /// Someなら試合を止めずに続け、終わった試合はここに入れる
+    replay_buffer: Option<ReplayBuffer>,
+    /// Someなら終わった試合の教師データをファイルに書き足す
+    example_writer: Option<ExampleWriter>,
+    /// 教師データの書き込みに失敗した理由。失敗したらそれ以降は書かない
+    example_write_error: Option<String>,
+    /// get_boards_for_predictionで返した盤面のスロット。receive_predictionの行と対応する
+    batch: Vec<usize>,
+    /// 次のバッチを探し始めるスロット。前のほうのスロットばかり評価しないようにする
+    next_slot: usize,
     train_examples: Vec<Vec<TrainExample>>,
+    /// Someならtrain_examplesの重複をまとめたもの
+    deduped: Option<ExampleBatch>,
+    resign_records: Vec<ResignRecord>,
+    /// フルサーチした教師データの数。prepare_nextが2を返すまではNone
     examples_count: Option<usize>,
+    /// trueにすると全スロットの探索を打ち切る
+    cancel: Arc<AtomicBool>,
+    stats: PlayStats,
 }
 
 impl SelfPlayer {
-    pub fn new(player_mode: PlayerMode, pool: &ThreadPool, mcts_args: &MctsArgs) -> Self {
+    pub fn new(
+        player_mode: PlayerMode,
+        pool: &ThreadPool,
+        mcts_args: &MctsArgs,
+        config: &SelfPlayConfig,
+    ) -> Self {
+        Self::with_opponent(player_mode, pool, mcts_args, config, None)
+    }
+
+    /// opponentを指定すると、その手番はNNを使わずにopponentが指す。2Playerモードでのみ使える
+    pub fn with_opponent(
+        player_mode: PlayerMode,
+        pool: &ThreadPool,
+        mcts_args: &MctsArgs,
+        config: &SelfPlayConfig,
+        opponent: Option<(Player, Opponent)>,
+    ) -> Self {
+        if opponent.is_some() && player_mode != PlayerMode::_2Player {
+            panic!("Opponent needs 2Player mode");
+        }
+        Self::spawn(
+            pool,
+            mcts_args,
+            config,
+            player_mode == PlayerMode::_2Player,
+            move |env| {
+                //一つの試合は同時に一つの盤面しか評価を待たないので、両プレイヤーで共有する
+                let pending = PendingEval::shared();
+                let p2_evaluator: Option<BoxedEvaluator> = if player_mode == PlayerMode::_2Player {
+                    Some(Box::new(env.evaluator(&pending, Player::PLAYER2)))
+                } else {
+                    None
+                };
+                let mut mcts = MctsContext::new(
+                    player_mode,
+                    Box::new(env.evaluator(&pending, Player::PLAYER1)),
+                    p2_evaluator,
+                    env.thread_id.clone(),
+                    env.mcts_args.clone(),
+                    env.analysis_slots.clone(),
+                );
+                mcts.tree_exports = Some(env.tree_exports.clone());
+                mcts.set_cancel(env.cancel.clone());
+                mcts.set_simulation_counter(env.simulations.clone());
+                if let Some((player, opponent)) = &opponent {
+                    opponent.install(&mut mcts, *player);
+                }
+                Box::new(GameTask::new(mcts, pending))
+            },
+        )
+    }
+
+    /// games_per_generationに関係なく試合を続け、終わった試合の教師データはcapacity個までリプレイバッファに溜める。
+    /// prepare_nextは2を返さない。学習のミニバッチはsample_replayで取り出す
+    pub fn continuous(
+        player_mode: PlayerMode,
+        pool: &ThreadPool,
+        mcts_args: &MctsArgs,
+        config: &SelfPlayConfig,
+        capacity: usize,
+    ) -> Self {
+        let config = SelfPlayConfig {
+            games_per_generation: usize::MAX,
+            ..*config
+        };
+        let mut sp = Self::new(player_mode, pool, mcts_args, &config);
+        //どのスロットのthread_idとも違う乱数列にする
+        let rng = ThreadID::new(config.num_slots()).create_rng(mcts_args.seed);
+        sp.replay_buffer = Some(ReplayBuffer::new(capacity, rng));
+        sp
+    }
+
+    /// 一つの局面をconcurrent_games個のレーンで共有する木で合計num_sims回探索する。評価は通常の試合と同じくバッチでPythonに送る。
+    /// 全レーンが終わるとprepare_nextが2を返し、結果はthread_id 0の解析結果として得られる
+    pub fn shared_search(
+        pool: &ThreadPool,
+        mcts_args: &MctsArgs,
+        config: &SelfPlayConfig,
+        board: OthelloBoard,
+        player: Player,
+        num_sims: usize,
+    ) -> Self {
+        //レーンは一度終わったら再開しない
+        let config = SelfPlayConfig {
+            games_per_generation: config.concurrent_games,
+            ..*config
+        };
+        let search = Arc::new(SharedSearch {
+            tree: SharedTree::new(mcts_args),
+            board,
+            player,
+            num_sims,
+            num_lanes: config.num_slots(),
+            started: AtomicUsize::new(0),
+            finished: AtomicUsize::new(0),
+        });
+        Self::spawn(pool, mcts_args, &config, false, move |env| {
+            let pending = PendingEval::shared();
+            Box::new(SharedSearchTask::new(
+                search.clone(),
+                env.evaluator(&pending, Player::PLAYER1),
+                pending,
+                env.analysis_slots.clone(),
+                env.cancel.clone(),
+                env.simulations.clone(),
+            ))
+        })
+    }
+
+    /// concurrent_games個のスロットでタスクを作り、プールのスレッド数のワーカーに振り分けて進める。
+    /// 各タスクは評価待ちになると盤面を、終わるとTrainExamplesをメインに送る。
+    /// 終わったスロットでは、games_per_generation個に達するまで次のタスクを作る
+    fn spawn<F>(
+        pool: &ThreadPool,
+        mcts_args: &MctsArgs,
+        config: &SelfPlayConfig,
+        per_player_cache: bool,
+        create_task: F,
+    ) -> Self
+    where
+        F: Fn(&TaskEnv) -> BoxedTask + Send + Sync + 'static,
+    {
+        let num_slots = config.num_slots();
+        let num_workers = pool.max_count().clamp(1, num_slots);
+        let create_task: Arc<TaskFactory> = Arc::new(create_task);
+        let quota = Arc::new(GameQuota {
+            started: AtomicUsize::new(num_slots),
+            total: config.games_per_generation,
+        });
         let mut thread_infos = vec![];
-        for index in 0..BATCH_SIZE {
+        let analysis_slots = AnalysisSlots::new(num_slots);
+        let tree_exports = TreeExportSlots::new(num_slots);
+        let eval_cache = Arc::new(EvalCache::new(mcts_args.eval_cache_size, per_player_cache));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let simulations = Arc::new(AtomicUsize::new(0));
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..num_workers)
+            .map(|_| mpsc::channel::<MainToThread>())
+            .unzip();
+        //どのスロットからでも受け取れるように、メインへの送信は一つのチャンネルにまとめる
+        let (send_to_main, receive_from_threads) = mpsc::channel::<ThreadToMain>();
+        let mut worker_slots: Vec<Vec<TaskSlot>> = (0..num_workers).map(|_| vec![]).collect();
+        for index in 0..num_slots {
             let thread_id = ThreadID::new(index);
-            let (send_to_main, receive_from_thread) = mpsc::channel::<ThreadToMain>();
-            let (send_to_thread, receiver_for_thread) = mpsc::channel::<MainToThread>();
             //thread_idとvecのindexが同値になるようにしている
             thread_infos.push(ThreadInfo {
-                send_to_thread,
-                receive_from_thread,
+                send_to_thread: senders[index % num_workers].clone(),
                 data: None,
+                closed: false,
+                playing: true,
             });
-            let mcts_args = mcts_args.clone();
-            pool.execute(move || {
-                let mut mcts = MctsContext::new(
-                    player_mode,
-                    send_to_main.clone(),
-                    receiver_for_thread,
-                    thread_id.clone(),
-                    mcts_args,
-                );
-                let r = mcts.execute_episode();
-                send_to_main
-                    .send(ThreadToMain::TrainExamples(r, thread_id))
-                    .unwrap();
+            let env = TaskEnv {
+                thread_id,
+                mcts_args: mcts_args.clone(),
+                analysis_slots: analysis_slots.clone(),
+                tree_exports: tree_exports.clone(),
+                eval_cache: eval_cache.clone(),
+                cancel: cancel.clone(),
+                simulations: simulations.clone(),
+            };
+            let task = create_task(&env);
+            worker_slots[index % num_workers].push(TaskSlot {
+                env,
+                send_to_main: send_to_main.clone(),
+                task,
             });
         }
+        for (slots, receiver) in worker_slots.into_iter().zip(receivers) {
+            let create_task = create_task.clone();
+            let quota = quota.clone();
+            pool.execute(move || run_worker(slots, receiver, num_workers, create_task, quota));
+        }
         Self {
+            config: *config,
             thread_infos,
+            receive_from_threads,
+            analysis_slots,
+            tree_exports,
+            eval_cache,
+            finished_games: vec![],
+            replay_buffer: None,
+            example_writer: None,
+            example_write_error: None,
+            batch: vec![],
+            next_slot: 0,
             train_examples: vec![],
+            deduped: None,
+            resign_records: vec![],
             examples_count: None,
+            cancel,
+            stats: PlayStats {
+                start: Instant::now(),
+                games: 0,
+                plies: 0,
+                positions: 0,
+                batches: 0,
+                simulations,
+            },
         }
     }
 
-    fn examples_flatten(&self) -> (impl Iterator<Item = &TrainExample>, usize) {
+    /// 進行中の試合をすべて打ち切り、ワーカーをプールに返す。途中の試合の教師データは捨てる。
+    /// その後のprepare_nextは、それまでに終わった試合を教師データとして2を返す。
+    /// 終わった試合がなければget_*_for_trainingは空の配列を返す
+    pub fn cancel(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        //送信側がなくなると、予測を待っているワーカーも終わる
+        self.thread_infos.clear();
+        self.batch.clear();
+        self.flush_examples();
+    }
+
+    /// フルサーチした手のみ返す(playout cap randomization)。重複をまとめていればまとめたものを返す。
+    /// トレーニング用のデータはすべてこれを使うので、各配列の行は一致する
+    fn examples_flatten(&self) -> (Box<dyn Iterator<Item = &TrainExample> + '_>, usize) {
         //くっそ汚い
+        if let Some(deduped) = &self.deduped {
+            return (Box::new(deduped.examples.iter()), deduped.len());
+        }
         (
-            self.train_examples.iter().flat_map(|a| a.iter()),
+            Box::new(
+                self.train_examples
+                    .iter()
+                    .flat_map(|a| a.iter())
+                    .filter(|e| e.is_full_search),
+            ),
             self.examples_count.unwrap(),
         )
     }
 
-    /// NスレッドでN個の試合を同時にシミュレーションしている。一手進めて盤面を返す
+    /// concurrent_games個の試合を同時にシミュレーションしている。一手進めて盤面を返す
     ///
     /// 最初の指し手は必ずplayer1とする。
     ///
@@ -81,99 +486,332 @@ impl SelfPlayer {
     /// 戻り値:
     /// 0: playerの取得できる盤面がない
     /// 1: 盤面の準備が出来た
-    /// 2: すべての試合が既に終わっていて、トレーニング用のデータの準備が出来た
+    /// 2: games_per_generation個の試合が終わっていて、トレーニング用のデータの準備が出来た
     pub fn prepare_next(&mut self, player: isize) -> usize {
         if self.train_examples.is_empty() == false {
             panic!("Train examples have been prepared. No need to do prepare_next()");
         }
 
-        for info in &mut self.thread_infos {
-            if info.data.is_none() {
-                info.data = Some(info.receive_from_thread.recv().unwrap())
+        let deadline = Instant::now() + self.config.batch_timeout;
+        //中止した後に届いたものは受け取らない
+        while !self.cancel.load(Ordering::Relaxed) {
+            while let Ok(message) = self.receive_from_threads.try_recv() {
+                self.receive_from_thread(message);
             }
+            let waiting = self
+                .thread_infos
+                .iter()
+                .filter(|info| info.data.is_none() && !info.closed)
+                .count();
+            if waiting == 0 {
+                break;
+            }
+            let ready = self.count_ready(player);
+            if self.config.min_batch_size.min(self.config.batch_size) <= ready {
+                return 1;
+            }
+            //揃っていなくても、待ち時間が過ぎたら揃った分だけ返す
+            let message = if ready == 0 {
+                self.receive_from_threads.recv().ok()
+            } else {
+                let now = Instant::now();
+                if deadline <= now {
+                    return 1;
+                }
+                match self.receive_from_threads.recv_timeout(deadline - now) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => return 1,
+                    Err(RecvTimeoutError::Disconnected) => None,
+                }
+            };
+            //ワーカーがすべて終わっていれば(パニックした場合も)、中止したものとして扱う
+            let Some(message) = message else {
+                self.cancel();
+                break;
+            };
+            self.receive_from_thread(message);
         }
 
         let mut all_training = true;
 
         for info in &self.thread_infos {
-            match &info.data {
-                Some(ThreadToMain::Board(_board, _id, p, _t)) => {
-                    if is_player(p, player) {
-                        return 1;
-                    } else {
-                        all_training = false;
-                    }
-                }
-                Some(ThreadToMain::TrainExamples(_, _)) => {}
-                None => {
-                    return 0;
+            if let Some(ThreadToMain::Board(_board, _id, p)) = &info.data {
+                if is_player(p, player) {
+                    return 1;
+                } else {
+                    all_training = false;
                 }
             }
         }
 
         if all_training {
-            let mut vec = vec![];
-            for info in &mut self.thread_infos {
-                if let ThreadToMain::TrainExamples(a, _) = info.data.take().unwrap() {
-                    vec.push(a);
-                } else {
-                    unreachable!()
-                }
-            }
-            self.train_examples = vec;
-            self.examples_count = Some(self.train_examples.iter().map(|a| a.len()).sum());
+            self.flush_examples();
+            self.train_examples = std::mem::take(&mut self.finished_games);
+            self.examples_count = Some(
+                self.train_examples
+                    .iter()
+                    .flat_map(|a| a.iter())
+                    .filter(|e| e.is_full_search)
+                    .count(),
+            );
             return 2;
         } else {
             return 0;
         }
     }
 
-    pub fn get_boards_for_prediction(&self, player: isize) -> CArray<f32> {
-        let mut r = CArray::<f32>::new3(BATCH_SIZE, N, N);
-
-        for info in &self.thread_infos {
-            match &info.data {
-                Some(ThreadToMain::Board(b, id, thinking_player, _t)) => {
-                    if is_player(thinking_player, player) {
-                        copy_board(r.ref_mut3_1(id.id()), b);
+    fn receive_from_thread(&mut self, message: ThreadToMain) {
+        match message {
+            ThreadToMain::Board(board, id, player) => {
+                let index = id.id();
+                self.thread_infos[index].data = Some(ThreadToMain::Board(board, id, player));
+                self.thread_infos[index].playing = true;
+            }
+            ThreadToMain::TrainExamples(episode, id) => {
+                self.thread_infos[id.id()].playing = false;
+                self.stats.games += 1;
+                self.stats.plies += episode.examples.len();
+                if let Some(writer) = &mut self.example_writer {
+                    if let Err(e) = writer.write_game(&episode.examples) {
+                        self.fail_example_writer(e);
+                    }
+                }
+                match &mut self.replay_buffer {
+                    Some(buffer) => buffer.push_game(episode.examples),
+                    None => {
+                        self.resign_records.push(episode.resign);
+                        self.finished_games.push(episode.examples);
                     }
                 }
-                _ => {}
+            }
+            ThreadToMain::Closed(id) => {
+                let info = &mut self.thread_infos[id.id()];
+                info.closed = true;
+                info.playing = false;
+            }
+        }
+    }
+
+    /// 途中のチャンクをファイルに書き出す
+    fn flush_examples(&mut self) {
+        if let Some(writer) = &mut self.example_writer {
+            if let Err(e) = writer.flush_chunk() {
+                self.fail_example_writer(e);
+            }
+        }
+    }
+
+    /// 書き込みに失敗したらwriterを捨て、理由を覚えておく
+    fn fail_example_writer(&mut self, e: io::Error) {
+        self.example_writer = None;
+        self.example_write_error = Some(e.to_string());
+    }
+
+    /// 評価を待っているplayerの盤面の数
+    fn count_ready(&self, player: isize) -> usize {
+        self.thread_infos
+            .iter()
+            .filter(|info| {
+                matches!(&info.data, Some(ThreadToMain::Board(_, _, p)) if is_player(p, player))
+            })
+            .count()
+    }
+
+    /// 評価を待っているplayerの盤面を最大batch_size個返す。行の数は実際に評価が必要な盤面の数になる
+    pub fn get_boards_for_prediction(&mut self, player: isize) -> CArray<f32> {
+        let len = self.thread_infos.len();
+        self.batch = (self.next_slot..len)
+            .chain(0..self.next_slot)
+            .filter(|&index| {
+                matches!(&self.thread_infos[index].data,
+                    Some(ThreadToMain::Board(_, _, thinking_player)) if is_player(thinking_player, player))
+            })
+            .take(self.config.batch_size)
+            .collect();
+        if let Some(&last) = self.batch.last() {
+            self.next_slot = (last + 1) % len;
+        }
+
+        let mut r = CArray::<f32>::new3(self.batch.len(), N, N);
+        for (row, &index) in self.batch.iter().enumerate() {
+            if let Some(ThreadToMain::Board(b, _id, _p)) = &self.thread_infos[index].data {
+                copy_board(r.ref_mut3_1(row), b);
             }
         }
 
         r
     }
 
+    /// 直前のget_boards_for_predictionで返した各行のthread_id
+    pub fn get_batch_thread_ids(&self) -> CArray<f32> {
+        let mut array = CArray::<f32>::new1(self.batch.len());
+        for (row, &index) in self.batch.iter().enumerate() {
+            array.as_mut()[row] = index as f32;
+        }
+        array
+    }
+
+    /// 直前のget_boards_for_predictionで返した盤面の順に予測を渡す。
+    /// 行の数が盤面の数と合わなければ何もせずfalseを返す
     pub fn receive_prediction(
         &mut self,
         pis: &CArray<f32>,
         win_rates: &CArray<f32>,
+        scores: Option<&CArray<f32>>,
         int_player: isize,
-    ) {
-        let predicts = PredictResult::convert_from_carrays(pis, win_rates);
-        for (predict, info) in predicts.into_iter().zip(self.thread_infos.iter_mut()) {
-            let b = if let Some(ThreadToMain::Board(_b, _id, p, _t)) = &info.data {
-                if is_player(p, int_player) {
-                    info.send_to_thread
-                        .send(MainToThread::Prediction(predict))
-                        .unwrap();
-                    true
-                } else {
-                    false
-                }
-            } else {
-                false
-            };
-
-            if b {
-                info.data = None
+    ) -> bool {
+        let rows = self.batch.len();
+        if pis.size0() != rows
+            || win_rates.size0() != rows
+            || scores.is_some_and(|scores| scores.size0() != rows)
+        {
+            return false;
+        }
+        let predicts = PredictResult::convert_from_carrays(pis, win_rates, scores);
+        let batch = std::mem::take(&mut self.batch);
+        self.stats.positions += batch.len();
+        self.stats.batches += 1;
+        for (predict, index) in predicts.into_iter().zip(batch) {
+            let info = &mut self.thread_infos[index];
+            if let Some(ThreadToMain::Board(_b, id, p)) = info.data.take() {
+                debug_assert!(is_player(&p, int_player));
+                //送れなければワーカーはもう終わっている。試合の後始末はprepare_nextで行う
+                let _ = info
+                    .send_to_thread
+                    .send(MainToThread::Prediction(predict, id));
             }
         }
+        true
+    }
+
+    /// そのスレッドが最後に探索したルートの合法手ごとの[action, visits, q, prior, ucb]
+    pub fn get_analysis_moves(&self, thread_id: usize) -> Option<CArray<f32>> {
+        let analysis = self.analysis_slots.get(thread_id)?;
+        let mut array = CArray::<f32>::new2(analysis.moves.len(), 5);
+        for (idx, m) in analysis.moves.iter().enumerate() {
+            array.ref_mut2(idx).copy_from_slice(&[
+                m.action._val() as f32,
+                m.visits as f32,
+                m.q,
+                m.prior,
+                m.ucb,
+            ]);
+        }
+        Some(array)
+    }
+
+    /// 読み筋のactionの列
+    pub fn get_analysis_pv(&self, thread_id: usize) -> Option<CArray<f32>> {
+        let analysis = self.analysis_slots.get(thread_id)?;
+        let mut array = CArray::<f32>::new1(analysis.pv.len());
+        for (idx, a) in analysis.pv.iter().enumerate() {
+            array.as_mut()[idx] = a._val() as f32;
+        }
+        Some(array)
+    }
+
+    pub fn get_analysis_root_value(&self, thread_id: usize) -> Option<f32> {
+        Some(self.analysis_slots.get(thread_id)?.root_value)
+    }
+
+    /// thread_idのスレッドが次に手を決めたとき、そのルートの探索木を書き出させる。
+    /// thread_idが範囲外ならfalse
+    pub fn request_tree_export(
+        &self,
+        thread_id: usize,
+        max_depth: usize,
+        min_visits: usize,
+    ) -> bool {
+        self.tree_exports.request(thread_id, max_depth, min_visits)
+    }
+
+    /// format 0: DOT, 1: JSON。要求した探索木がまだ書き出されていなければNone
+    pub fn get_tree_export(&self, thread_id: usize, format: usize) -> Option<String> {
+        let tree = self.tree_exports.get(thread_id)?;
+        match format {
+            0 => Some(tree.to_dot()),
+            1 => Some(tree.to_json()),
+            _ => None,
+        }
+    }
+
+    /// これから終わる試合の教師データを、pathにchunk_size個ずつのチャンクで書き足す
+    pub fn write_examples_to<P: AsRef<Path>>(&mut self, path: P, chunk_size: usize) -> io::Result<()> {
+        self.example_writer = Some(ExampleWriter::open(path, chunk_size)?);
+        Ok(())
+    }
+
+    /// 教師データの書き込みに失敗していればその理由
+    pub fn example_write_error(&self) -> Option<&str> {
+        self.example_write_error.as_deref()
+    }
+
+    /// リプレイバッファから学習のミニバッチを取り出す。continuousでなければNone
+    pub fn replay_buffer(&mut self) -> Option<&mut ReplayBuffer> {
+        self.replay_buffer.as_mut()
+    }
+
+    pub fn clear_eval_cache(&self) {
+        self.eval_cache.clear();
+    }
+
+    /// 予測に使うNNの重みの版。前と違えば予測結果のキャッシュを捨てる
+    pub fn set_network_version(&self, version: u64) {
+        self.eval_cache.set_network_version(version);
+    }
+
+    /// [キャッシュヒット数, ミス数]
+    pub fn get_eval_cache_stats(&self) -> CArray<f32> {
+        let mut array = CArray::<f32>::new1(2);
+        array.as_mut().copy_from_slice(&[
+            self.eval_cache.hits() as f32,
+            self.eval_cache.misses() as f32,
+        ]);
+        array
+    }
+
+    /// [終わった試合数, 進行中の試合数, 終わった試合の平均手数, 評価した盤面数, NNへの要求(バッチ)数,
+    /// 評価キャッシュのヒット数, 1秒あたりのシミュレーション数, バッチの平均充填率]。
+    /// 毎バッチ呼んでも重くないので、止まっていないかの確認に使える
+    pub fn get_stats(&self) -> CArray<f32> {
+        let stats = &self.stats;
+        let in_flight = self
+            .thread_infos
+            .iter()
+            .filter(|info| info.playing)
+            .count();
+        let average_ply = if stats.games == 0 {
+            0.0
+        } else {
+            stats.plies as f32 / stats.games as f32
+        };
+        let elapsed = stats.start.elapsed().as_secs_f32();
+        let sims_per_sec = if elapsed == 0.0 {
+            0.0
+        } else {
+            stats.simulations.load(Ordering::Relaxed) as f32 / elapsed
+        };
+        let batch_fill = if stats.batches == 0 {
+            0.0
+        } else {
+            stats.positions as f32 / (stats.batches * self.config.batch_size) as f32
+        };
+        let mut array = CArray::<f32>::new1(8);
+        array.as_mut().copy_from_slice(&[
+            stats.games as f32,
+            in_flight as f32,
+            average_ply,
+            stats.positions as f32,
+            stats.batches as f32,
+            self.eval_cache.hits() as f32,
+            sims_per_sec,
+            batch_fill,
+        ]);
+        array
     }
 
     pub fn get_pis_for_training(&self) -> CArray<f32> {
-        if self.train_examples.is_empty() {
+        if self.examples_count.is_none() {
             panic!("train_examples is not prepared");
         }
         let (examples, len) = self.examples_flatten();
@@ -187,7 +825,7 @@ impl SelfPlayer {
     }
 
     pub fn get_boards_for_training(&self) -> CArray<f32> {
-        if self.train_examples.is_empty() {
+        if self.examples_count.is_none() {
             panic!("train_examples is not prepared");
         }
         let (examples, len) = self.examples_flatten();
@@ -200,7 +838,7 @@ impl SelfPlayer {
     }
 
     pub fn get_players_for_training(&mut self) -> CArray<f32> {
-        if self.train_examples.is_empty() {
+        if self.examples_count.is_none() {
             panic!("train_examples is not prepared");
         }
         let (examples, len) = self.examples_flatten();
@@ -213,7 +851,7 @@ impl SelfPlayer {
     }
 
     pub fn get_results_for_training(&mut self) -> CArray<f32> {
-        if self.train_examples.is_empty() {
+        if self.examples_count.is_none() {
             panic!("train_examples is not prepared");
         }
         let (examples, len) = self.examples_flatten();
@@ -226,8 +864,100 @@ impl SelfPlayer {
         array
     }
 
+    /// playerから見た最終的な石差をマスの数で割ったもの(-1..1)
+    pub fn get_scores_for_training(&mut self) -> CArray<f32> {
+        if self.examples_count.is_none() {
+            panic!("train_examples is not prepared");
+        }
+        let (examples, len) = self.examples_flatten();
+
+        let mut array = CArray::<f32>::new1(len);
+
+        for (idx, example) in examples.enumerate() {
+            array.as_mut()[idx] = example.score;
+        }
+        array
+    }
+
+    /// 同じ局面の教師データを一つにまとめ、pi、value_target、scoreを平均する。
+    /// 以後のget_*_for_trainingはまとめたものを返す。symmetryがtrueなら対称な盤面もまとめる。まとめた後の数を返す
+    pub fn dedup_train_examples(&mut self, symmetry: bool) -> usize {
+        if self.examples_count.is_none() {
+            panic!("train_examples is not prepared");
+        }
+        let deduped = dedup(self.examples_flatten().0, symmetry);
+        let len = deduped.len();
+        self.deduped = Some(deduped);
+        len
+    }
+
+    /// 学習のサンプルの重み。重複をまとめていなければすべて1
+    pub fn get_weights_for_training(&self) -> CArray<f32> {
+        if self.examples_count.is_none() {
+            panic!("train_examples is not prepared");
+        }
+        match &self.deduped {
+            Some(deduped) => deduped.get_weights(),
+            None => {
+                let mut array = CArray::<f32>::new1(self.examples_count.unwrap());
+                array.as_mut().fill(1.0);
+                array
+            }
+        }
+    }
+
+    /// resultとルートの探索QをMctsArgs::value_targetで混ぜたもの
+    pub fn get_value_targets_for_training(&mut self) -> CArray<f32> {
+        if self.examples_count.is_none() {
+            panic!("train_examples is not prepared");
+        }
+        let (examples, len) = self.examples_flatten();
+
+        let mut array = CArray::<f32>::new1(len);
+
+        for (idx, example) in examples.enumerate() {
+            array.as_mut()[idx] = example.value_target;
+        }
+        array
+    }
+
+    /// [投了した試合数, 投了禁止の試合で投了するはずだった試合数, そのうち実際は勝った試合数, 誤投了率]
+    pub fn get_resign_stats(&self) -> CArray<f32> {
+        if self.examples_count.is_none() {
+            panic!("train_examples is not prepared");
+        }
+        let mut resigned = 0;
+        let mut control = 0;
+        let mut false_positives = 0;
+        for record in &self.resign_records {
+            match record {
+                ResignRecord::NotResigned => {}
+                ResignRecord::Resigned => resigned += 1,
+                ResignRecord::Control { would_have_lost } => {
+                    control += 1;
+                    if !would_have_lost {
+                        false_positives += 1;
+                    }
+                }
+            }
+        }
+        let rate = if control == 0 {
+            0.0
+        } else {
+            false_positives as f32 / control as f32
+        };
+        let mut array = CArray::<f32>::new1(4);
+        array.as_mut().copy_from_slice(&[
+            resigned as f32,
+            control as f32,
+            false_positives as f32,
+            rate,
+        ]);
+        array
+    }
+
     pub fn get_results_for_counting(&mut self) -> CArray<f32> {
-        if self.train_examples.is_empty() {
+        if self.examples_count.is_none() {
             panic!("train_examples is not prepared");
         }
         let example_len = self.train_examples.len();
@@ -240,11 +970,18 @@ impl SelfPlayer {
     }
 }
 
+/// 捨てるときも試合を打ち切り、ワーカーがプールに戻るようにする
+impl Drop for SelfPlayer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
 fn is_player(player: &Player, int_player: isize) -> bool {
     int_player == 0 || int_player == player.color() as isize
 }
 
-fn copy_board(slice: &mut [f32], board: &OthelloBoard) {
+pub fn copy_board(slice: &mut [f32], board: &OthelloBoard) {
     let b = board.0.as_flattened();
     for i in 0..b.len() {
         slice[i] = b[i] as f32;
@@ -265,11 +1002,163 @@ pub extern "C" fn create_self_player(
         return 0 as *mut SelfPlayer;
     };
     unsafe {
-        let b = Box::new(SelfPlayer::new(player_mode, &(*p).pool, &(*p).mcts_args));
+        let b = Box::new(SelfPlayer::new(
+            player_mode,
+            &(*p).pool,
+            &(*p).mcts_args,
+            &(*p).self_play,
+        ));
+        if let Some(seed) = &mut (*p).mcts_args.seed {
+            *seed = seed.wrapping_add(1);
+        }
+        Box::into_raw(b)
+    }
+}
+
+/// 試合を止めずに続け、終わった試合の教師データをcapacity個までリプレイバッファに溜めるSelfPlayerを作る。
+/// player_modeは1か2で、capacityは1以上。それ以外の場合NULL POINTER(0)が返る
+#[no_mangle]
+pub extern "C" fn create_continuous_self_player(
+    p: *mut PyCommunicator,
+    player_mode: usize,
+    capacity: usize,
+) -> *mut SelfPlayer {
+    let player_mode = if player_mode == 1 {
+        PlayerMode::_1Player
+    } else if player_mode == 2 {
+        PlayerMode::_2Player
+    } else {
+        return std::ptr::null_mut();
+    };
+    if capacity == 0 {
+        return std::ptr::null_mut();
+    }
+    unsafe {
+        let b = Box::new(SelfPlayer::continuous(
+            player_mode,
+            &(*p).pool,
+            &(*p).mcts_args,
+            &(*p).self_play,
+            capacity,
+        ));
+        if let Some(seed) = &mut (*p).mcts_args.seed {
+            *seed = seed.wrapping_add(1);
+        }
+        Box::into_raw(b)
+    }
+}
+
+/// 2PlayerモードのSelfPlayerを作り、opponent_playerの手番はopponentに指させる。
+/// opponent_playerは1か-1。それ以外の場合NULL POINTER(0)が返る
+fn create_self_player_vs(
+    p: *mut PyCommunicator,
+    opponent_player: isize,
+    opponent: Opponent,
+) -> *mut SelfPlayer {
+    let opponent_player = if opponent_player == 1 {
+        Player::PLAYER1
+    } else if opponent_player == -1 {
+        Player::PLAYER2
+    } else {
+        return std::ptr::null_mut();
+    };
+    unsafe {
+        let b = Box::new(SelfPlayer::with_opponent(
+            PlayerMode::_2Player,
+            &(*p).pool,
+            &(*p).mcts_args,
+            &(*p).self_play,
+            Some((opponent_player, opponent)),
+        ));
+        if let Some(seed) = &mut (*p).mcts_args.seed {
+            *seed = seed.wrapping_add(1);
+        }
         Box::into_raw(b)
     }
 }
 
+/// uct_playerの手番は、ランダムプレイアウトのUCTが指す
+#[no_mangle]
+pub extern "C" fn create_self_player_vs_uct(
+    p: *mut PyCommunicator,
+    uct_player: isize,
+    num_sims: usize,
+    num_rollouts: usize,
+) -> *mut SelfPlayer {
+    create_self_player_vs(
+        p,
+        uct_player,
+        Opponent::Uct {
+            num_sims,
+            num_rollouts,
+        },
+    )
+}
+
+/// alpha_beta_playerの手番は、アルファベータ探索が指す。
+/// depthが0なら深さの制限なし、time_msが0なら時間の制限なし。両方0ならNULL POINTER(0)が返る
+#[no_mangle]
+pub extern "C" fn create_self_player_vs_alpha_beta(
+    p: *mut PyCommunicator,
+    alpha_beta_player: isize,
+    depth: usize,
+    time_ms: u64,
+) -> *mut SelfPlayer {
+    if depth == 0 && time_ms == 0 {
+        return std::ptr::null_mut();
+    }
+    create_self_player_vs(
+        p,
+        alpha_beta_player,
+        Opponent::AlphaBeta {
+            depth: (depth != 0).then_some(depth),
+            time: (time_ms != 0).then(|| Duration::from_millis(time_ms)),
+        },
+    )
+}
+
+/// boardの局面(N×N、1が黒、-1が白)をconcurrent_games個のレーンで共有する木で探索するSelfPlayerを作る。
+/// playerは1か-1。それ以外の場合や、boardがN×Nでないか-1、0、1以外の値を含む場合はNULL POINTER(0)が返る
+#[no_mangle]
+pub extern "C" fn create_shared_search(
+    p: *mut PyCommunicator,
+    board: *const CArray<f32>,
+    player: isize,
+    num_sims: usize,
+) -> *mut SelfPlayer {
+    let player = if player == 1 {
+        Player::PLAYER1
+    } else if player == -1 {
+        Player::PLAYER2
+    } else {
+        return std::ptr::null_mut();
+    };
+    unsafe {
+        if board.is_null() || (*board).size() != [N, N] {
+            return std::ptr::null_mut();
+        }
+        let mut b = OthelloBoard::new();
+        for x in 0..N {
+            for y in 0..N {
+                let v = (*board).get2(x, y);
+                if v != 1.0 && v != 0.0 && v != -1.0 {
+                    return std::ptr::null_mut();
+                }
+                b[x][y] = v as i32;
+            }
+        }
+        let sp = Box::new(SelfPlayer::shared_search(
+            &(*p).pool,
+            &(*p).mcts_args,
+            &(*p).self_play,
+            b,
+            player,
+            num_sims,
+        ));
+        Box::into_raw(sp)
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn destroy_self_player(p: *mut SelfPlayer) {
     unsafe {
@@ -277,7 +1166,13 @@ pub extern "C" fn destroy_self_player(p: *mut SelfPlayer) {
     }
 }
 
-/// NスレッドでN個の試合を同時にシミュレーションしている。一手進めて盤面を返す
+/// 進行中の試合をすべて打ち切る。その後のprepare_nextは終わっていた試合だけで2を返す
+#[no_mangle]
+pub extern "C" fn self_player_cancel(p: *mut SelfPlayer) {
+    unsafe { (*p).cancel() }
+}
+
+/// concurrent_games個の試合を同時にシミュレーションしている。一手進めて盤面を返す
 ///
 /// 最初の指し手は必ずplayer1とする。
 ///
@@ -289,12 +1184,13 @@ pub extern "C" fn destroy_self_player(p: *mut SelfPlayer) {
 /// 戻り値:
 /// 0: そのプレイヤーが全部passであったりして、返すべき盤面がない
 /// 1: 盤面の準備が出来た
-/// 2: すべての試合が既に終わっていて、トレーニング用のデータの準備が出来た
+/// 2: games_per_generation個の試合が終わっていて、トレーニング用のデータの準備が出来た
 #[no_mangle]
 pub extern "C" fn self_player_prepare_next(p: *mut SelfPlayer, player: isize) -> usize {
     unsafe { (*p).prepare_next(player) }
 }
 
+/// 行の数は最大batch_sizeで、実際に評価が必要な盤面の数になる
 #[no_mangle]
 pub extern "C" fn self_player_get_boards_for_prediction(
     p: *mut SelfPlayer,
@@ -306,6 +1202,199 @@ pub extern "C" fn self_player_get_boards_for_prediction(
     }
 }
 
+/// まだ解析結果がなければNULL POINTER(0)が返る
+#[no_mangle]
+pub extern "C" fn self_player_get_analysis_moves(
+    p: *mut SelfPlayer,
+    thread_id: usize,
+) -> *mut CArray<f32> {
+    unsafe {
+        match (*p).get_analysis_moves(thread_id) {
+            Some(array) => Box::into_raw(Box::new(array)),
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// まだ解析結果がなければNULL POINTER(0)が返る
+#[no_mangle]
+pub extern "C" fn self_player_get_analysis_pv(
+    p: *mut SelfPlayer,
+    thread_id: usize,
+) -> *mut CArray<f32> {
+    unsafe {
+        match (*p).get_analysis_pv(thread_id) {
+            Some(array) => Box::into_raw(Box::new(array)),
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// まだ解析結果がなければNaNが返る
+#[no_mangle]
+pub extern "C" fn self_player_get_analysis_root_value(p: *mut SelfPlayer, thread_id: usize) -> f32 {
+    unsafe { (*p).get_analysis_root_value(thread_id).unwrap_or(f32::NAN) }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_request_tree_export(
+    p: *mut SelfPlayer,
+    thread_id: usize,
+    max_depth: usize,
+    min_visits: usize,
+) -> bool {
+    unsafe { (*p).request_tree_export(thread_id, max_depth, min_visits) }
+}
+
+/// 戻り値の文字列はdestroy_c_stringで解放する。まだ書き出されていなければNULL POINTER(0)
+#[no_mangle]
+pub extern "C" fn self_player_get_tree_export(
+    p: *mut SelfPlayer,
+    thread_id: usize,
+    format: usize,
+) -> *mut c_char {
+    unsafe {
+        match (*p).get_tree_export(thread_id, format) {
+            Some(s) => to_c_string(&s),
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// destroy_c_stringで解放する文字列を作る。途中のNUL文字はU+FFFDに置き換える
+pub(crate) fn to_c_string(s: &str) -> *mut c_char {
+    CString::new(s.replace('\0', "\u{FFFD}"))
+        .unwrap()
+        .into_raw()
+}
+
+/// errorがNULL POINTERでなければmessageを書く
+pub(crate) fn write_c_error(error: *mut *mut c_char, message: &str) {
+    if !error.is_null() {
+        unsafe {
+            *error = to_c_string(message);
+        }
+    }
+}
+
+/// NULL POINTERなら何もしない
+#[no_mangle]
+pub extern "C" fn destroy_c_string(p: *mut c_char) {
+    if p.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = CString::from_raw(p);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_clear_eval_cache(p: *mut SelfPlayer) {
+    unsafe { (*p).clear_eval_cache() }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_set_network_version(p: *mut SelfPlayer, version: u64) {
+    unsafe { (*p).set_network_version(version) }
+}
+
+/// [終わった試合数, 進行中の試合数, 平均手数, 評価した盤面数, NNへの要求数, キャッシュヒット数,
+/// 1秒あたりのシミュレーション数, バッチの平均充填率]
+#[no_mangle]
+pub extern "C" fn self_player_stats(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_stats());
+        Box::into_raw(b)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_eval_cache_stats(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_eval_cache_stats());
+        Box::into_raw(b)
+    }
+}
+
+/// これから終わる試合の教師データを、pathのファイルにchunk_size個ずつのチャンクで書き足す。
+/// ファイルを開けなければfalse
+#[no_mangle]
+pub extern "C" fn self_player_write_examples(
+    p: *mut SelfPlayer,
+    path: *const c_char,
+    chunk_size: usize,
+) -> bool {
+    unsafe {
+        match CStr::from_ptr(path).to_str() {
+            Ok(path) => (*p).write_examples_to(path, chunk_size).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// 教師データの書き込みに失敗していればその理由。戻り値はdestroy_c_stringで解放する。
+/// 失敗していなければNULL POINTER(0)
+#[no_mangle]
+pub extern "C" fn self_player_get_example_write_error(p: *mut SelfPlayer) -> *mut c_char {
+    unsafe {
+        match (*p).example_write_error() {
+            Some(s) => to_c_string(s),
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// リプレイバッファからnum個(溜まっていなければ全部)取り出し、取り出した数を返す。
+/// 結果はself_player_get_replay_*で得る。continuousでなければ0
+#[no_mangle]
+pub extern "C" fn self_player_sample_replay(p: *mut SelfPlayer, num: usize) -> usize {
+    unsafe { (*p).replay_buffer().map_or(0, |buffer| buffer.sample(num)) }
+}
+
+fn replay_array<F>(p: *mut SelfPlayer, f: F) -> *mut CArray<f32>
+where
+    F: Fn(&ReplayBuffer) -> CArray<f32>,
+{
+    unsafe {
+        match (*p).replay_buffer() {
+            Some(buffer) => Box::into_raw(Box::new(f(buffer))),
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// continuousでなければNULL POINTER(0)が返る。以下のself_player_get_replay_*も同じ
+#[no_mangle]
+pub extern "C" fn self_player_get_replay_pis(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    replay_array(p, |buffer| buffer.sampled().get_pis())
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_replay_boards(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    replay_array(p, |buffer| buffer.sampled().get_boards())
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_replay_players(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    replay_array(p, |buffer| buffer.sampled().get_players())
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_replay_value_targets(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    replay_array(p, |buffer| buffer.sampled().get_value_targets())
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_replay_scores(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    replay_array(p, |buffer| buffer.sampled().get_scores())
+}
+
+/// [溜まっている教師データの数, これまでに入れた試合の数, これまでに入れた教師データの数]
+#[no_mangle]
+pub extern "C" fn self_player_get_replay_stats(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    replay_array(p, ReplayBuffer::get_stats)
+}
+
 #[no_mangle]
 pub extern "C" fn self_player_get_pis_for_training(p: *mut SelfPlayer) -> *mut CArray<f32> {
     unsafe {
@@ -338,6 +1427,46 @@ pub extern "C" fn self_player_get_results_for_training(p: *mut SelfPlayer) -> *m
     }
 }
 
+#[no_mangle]
+pub extern "C" fn self_player_get_scores_for_training(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_scores_for_training());
+        Box::into_raw(b)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_value_targets_for_training(
+    p: *mut SelfPlayer,
+) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_value_targets_for_training());
+        Box::into_raw(b)
+    }
+}
+
+/// 同じ局面の教師データをまとめ、まとめた後の数を返す。symmetryがtrueなら対称な盤面もまとめる
+#[no_mangle]
+pub extern "C" fn self_player_dedup_train_examples(p: *mut SelfPlayer, symmetry: bool) -> usize {
+    unsafe { (*p).dedup_train_examples(symmetry) }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_weights_for_training(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_weights_for_training());
+        Box::into_raw(b)
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn self_player_get_resign_stats(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_resign_stats());
+        Box::into_raw(b)
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn self_player_get_results_for_counting(p: *mut SelfPlayer) -> *mut CArray<f32> {
     unsafe {
@@ -346,14 +1475,26 @@ pub extern "C" fn self_player_get_results_for_counting(p: *mut SelfPlayer) -> *m
     }
 }
 
+/// 直前のself_player_get_boards_for_predictionの各行のthread_id
+#[no_mangle]
+pub extern "C" fn self_player_get_batch_thread_ids(p: *mut SelfPlayer) -> *mut CArray<f32> {
+    unsafe {
+        let b = Box::new((*p).get_batch_thread_ids());
+        Box::into_raw(b)
+    }
+}
+
+/// 直前のself_player_get_boards_for_predictionの行の順に予測を渡す。
+/// scoresはスコアヘッドがなければNULL POINTER(0)でよい。行の数が合わなければfalse
 #[no_mangle]
 pub extern "C" fn self_player_receive_prediction(
     p: *mut SelfPlayer,
     pis: *mut CArray<f32>,
     win_rates: *mut CArray<f32>,
+    scores: *mut CArray<f32>,
     player: isize,
-) {
+) -> bool {
     unsafe {
-        (*p).receive_prediction(&*pis, &*win_rates, player);
+        (*p).receive_prediction(&*pis, &*win_rates, scores.as_ref(), player)
     }
 }
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::action::{Action, Pi, ValidMoves};
+use crate::analysis::{Analysis, MoveAnalysis};
+use crate::constant::{EPS, MOVE_LEN};
+use crate::evaluator::{BoxedEvaluator, Evaluator};
+use crate::mcts::{canonical_game_end, combined_utility, resolve_tie, terminal_utility};
+use crate::mcts_args::MctsArgs;
+use crate::othello_board::OthelloBoard;
+use crate::othello_game::{get_next_state, get_valid_moves};
+use crate::player::Player;
+use crate::predict_result::PredictResult;
+
+const NUM_SHARDS: usize = 16;
+/// 価値の和は固定小数点でアトミックに足す
+const VALUE_SCALE: f32 = (1 << 20) as f32;
+/// 探索中の手は、この価値で負けたものとして扱う(virtual loss)
+const VIRTUAL_LOSS: f32 = 1.0;
+
+#[derive(Default)]
+struct SharedEdge {
+    visits: AtomicUsize,
+    /// 手を指した側から見た価値の和 * VALUE_SCALE
+    value_sum: AtomicI64,
+    /// この手を通って評価待ちになっているシミュレーションの数
+    virtual_loss: AtomicUsize,
+}
+
+impl SharedEdge {
+    fn visits(&self) -> usize {
+        self.visits.load(Ordering::Acquire)
+    }
+
+    fn q(&self) -> f32 {
+        let visits = self.visits();
+        if visits == 0 {
+            0.0
+        } else {
+            self.value_sum.load(Ordering::Acquire) as f32 / VALUE_SCALE / visits as f32
+        }
+    }
+}
+
+struct SharedNode {
+    prior: Pi,
+    valid_moves: ValidMoves,
+    visits: AtomicUsize,
+    edges: Vec<SharedEdge>,
+}
+
+impl SharedNode {
+    /// virtual lossを含めたPUCT
+    fn puct(&self, cpuct: f32, a: usize) -> f32 {
+        let edge = &self.edges[a];
+        let visits = edge.visits();
+        let virtual_loss = edge.virtual_loss.load(Ordering::Acquire);
+        let n = visits + virtual_loss;
+        let parent = self.visits.load(Ordering::Acquire) as f32;
+        if n == 0 {
+            cpuct * self.prior[a] * (parent + EPS).sqrt()
+        } else {
+            let sum = edge.value_sum.load(Ordering::Acquire) as f32 / VALUE_SCALE
+                - VIRTUAL_LOSS * virtual_loss as f32;
+            sum / n as f32 + cpuct * self.prior[a] * parent.sqrt() / (1.0 + n as f32)
+        }
+    }
+}
+
+/// 評価待ちの葉と、そこまでの経路。経路の手にはvirtual lossが掛かったまま
+pub struct PendingSimulation {
+    path: Vec<(Arc<SharedNode>, usize)>,
+    s: u128,
+    canonical_board: OthelloBoard,
+}
+
+/// 複数のスレッドで一つの局面を探索するための木。訪問回数と価値の和はアトミックに更新する。
+/// canonical boardのハッシュで引く
+pub struct SharedTree {
+    shards: Vec<Mutex<HashMap<u128, Arc<SharedNode>>>>,
+    cpuct: f32,
+    score_utility_weight: f32,
+}
+
+impl SharedTree {
+    pub fn new(args: &MctsArgs) -> Self {
+        Self {
+            shards: (0..NUM_SHARDS)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            cpuct: args.cpuct,
+            score_utility_weight: args.score_utility_weight,
+        }
+    }
+
+    fn shard(&self, s: u128) -> &Mutex<HashMap<u128, Arc<SharedNode>>> {
+        &self.shards[(s ^ (s >> 64)) as usize % NUM_SHARDS]
+    }
+
+    fn get(&self, s: u128) -> Option<Arc<SharedNode>> {
+        self.shard(s).lock().unwrap().get(&s).cloned()
+    }
+
+    /// 評価中に他のスレッドが同じ局面を展開していたら、そちらを使う
+    fn expand(&self, s: u128, canonical_board: &OthelloBoard, r: PredictResult) -> f32 {
+        let mut prior = r.action_probs;
+        let valid_moves = get_valid_moves(canonical_board, Player::PLAYER1);
+        valid_moves.apply(&mut prior);
+        let sum: f32 = prior.probs().iter().sum();
+        for a in 0..MOVE_LEN {
+            prior[a] = if 0.0 < sum {
+                prior[a] / sum
+            } else if valid_moves[a] {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        let node = SharedNode {
+            prior,
+            valid_moves,
+            visits: AtomicUsize::new(0),
+            edges: (0..MOVE_LEN).map(|_| SharedEdge::default()).collect(),
+        };
+        self.shard(s)
+            .lock()
+            .unwrap()
+            .entry(s)
+            .or_insert_with(|| Arc::new(node));
+        combined_utility(r.win_rate, r.score.unwrap_or(0.0), self.score_utility_weight)
+    }
+
+    /// 一回のシミュレーション。選んだ手にvirtual lossを掛けながら葉まで降り、評価して戻る
+    pub fn simulate(
+        &self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        evaluator: &mut dyn Evaluator,
+    ) {
+        let pending = self.try_simulate(unorthodox_board, player, evaluator);
+        assert!(
+            pending.is_none(),
+            "evaluator suspended a blocking simulation"
+        );
+    }
+
+    /// 葉の評価が得られなければ、経路を返して中断する。続きはresume_simulationで行う
+    pub fn try_simulate(
+        &self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        evaluator: &mut dyn Evaluator,
+    ) -> Option<PendingSimulation> {
+        let mut board = unorthodox_board.clone();
+        let mut player = player;
+        let mut path: Vec<(Arc<SharedNode>, usize)> = vec![];
+
+        //葉の手番側から見た価値
+        let value = loop {
+            let canonical_board = board.create_canonical_board(player);
+            let game_end = resolve_tie(canonical_game_end(&canonical_board), player);
+            if game_end != 0 {
+                break terminal_utility(&canonical_board, game_end, self.score_utility_weight);
+            }
+            let s = canonical_board.string_representation();
+            let Some(node) = self.get(s) else {
+                let pending = PendingSimulation {
+                    path,
+                    s,
+                    canonical_board,
+                };
+                return self.resume_simulation(pending, evaluator);
+            };
+            let mut best = f32::NEG_INFINITY;
+            let mut best_a = 0;
+            for a in 0..MOVE_LEN {
+                if node.valid_moves[a] {
+                    let u = node.puct(self.cpuct, a);
+                    if best < u {
+                        best = u;
+                        best_a = a;
+                    }
+                }
+            }
+            node.visits.fetch_add(1, Ordering::AcqRel);
+            node.edges[best_a]
+                .virtual_loss
+                .fetch_add(1, Ordering::AcqRel);
+            get_next_state(&mut board, player, Action::new(best_a));
+            player = player.other();
+            path.push((node, best_a));
+        };
+        self.backup(&path, value);
+        None
+    }
+
+    pub fn resume_simulation(
+        &self,
+        pending: PendingSimulation,
+        evaluator: &mut dyn Evaluator,
+    ) -> Option<PendingSimulation> {
+        let Some(r) = evaluator.try_evaluate(&pending.canonical_board) else {
+            return Some(pending);
+        };
+        let value = self.expand(pending.s, &pending.canonical_board, r);
+        self.backup(&pending.path, value);
+        None
+    }
+
+    /// valueは葉の手番側から見た価値。経路のvirtual lossを外す
+    fn backup(&self, path: &[(Arc<SharedNode>, usize)], value: f32) {
+        let mut value = value;
+        for (node, a) in path.iter().rev() {
+            //手を指した側から見た価値にする
+            value = -value;
+            let edge = &node.edges[*a];
+            edge.value_sum
+                .fetch_add((value * VALUE_SCALE) as i64, Ordering::AcqRel);
+            edge.visits.fetch_add(1, Ordering::AcqRel);
+            edge.virtual_loss.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    /// 一つの局面を、evaluatorsの数のスレッドで合計num_sims回探索する
+    pub fn search_parallel(
+        &self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        num_sims: usize,
+        evaluators: Vec<BoxedEvaluator>,
+    ) -> Analysis {
+        let started = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for mut evaluator in evaluators {
+                let started = &started;
+                scope.spawn(move || {
+                    while started.fetch_add(1, Ordering::AcqRel) < num_sims {
+                        self.simulate(unorthodox_board, player, evaluator.as_mut());
+                    }
+                });
+            }
+        });
+        self.analyze(unorthodox_board, player)
+    }
+
+    pub fn analyze(&self, unorthodox_board: &OthelloBoard, player: Player) -> Analysis {
+        let s = unorthodox_board
+            .create_canonical_board(player)
+            .string_representation();
+        let Some(node) = self.get(s) else {
+            return Analysis {
+                moves: vec![],
+                pv: vec![],
+                root_value: 0.0,
+            };
+        };
+        let moves: Vec<MoveAnalysis> = (0..MOVE_LEN)
+            .filter(|&a| node.valid_moves[a])
+            .map(|a| MoveAnalysis {
+                action: Action::new(a),
+                visits: node.edges[a].visits(),
+                q: node.edges[a].q(),
+                prior: node.prior[a],
+                ucb: node.puct(self.cpuct, a),
+            })
+            .collect();
+        let visits: usize = moves.iter().map(|m| m.visits).sum();
+        let root_value = if visits == 0 {
+            0.0
+        } else {
+            moves.iter().map(|m| m.q * m.visits as f32).sum::<f32>() / visits as f32
+        };
+        Analysis {
+            moves,
+            pv: self.principal_variation(unorthodox_board, player),
+            root_value,
+        }
+    }
+
+    fn principal_variation(&self, unorthodox_board: &OthelloBoard, player: Player) -> Vec<Action> {
+        let mut board = unorthodox_board.clone();
+        let mut player = player;
+        let mut pv = vec![];
+        loop {
+            let s = board.create_canonical_board(player).string_representation();
+            let Some(node) = self.get(s) else {
+                return pv;
+            };
+            let Some(a) = (0..MOVE_LEN)
+                .filter(|&a| node.edges[a].visits() != 0)
+                .max_by_key(|&a| node.edges[a].visits())
+            else {
+                return pv;
+            };
+            pv.push(Action::new(a));
+            get_next_state(&mut board, player, Action::new(a));
+            player = player.other();
+        }
+    }
+
+    /// 全ノードについて、(ノードの訪問回数, 子の訪問回数の和, 残っているvirtual lossの和)を返す。
+    /// 探索が終わった後は、前の二つが一致し、virtual lossは0になるはず
+    pub fn _consistency(&self) -> Vec<(usize, usize, usize)> {
+        let mut r = vec![];
+        for shard in &self.shards {
+            for node in shard.lock().unwrap().values() {
+                r.push((
+                    node.visits.load(Ordering::Acquire),
+                    node.edges.iter().map(|e| e.visits()).sum(),
+                    node.edges
+                        .iter()
+                        .map(|e| e.virtual_loss.load(Ordering::Acquire))
+                        .sum(),
+                ));
+            }
+        }
+        r
+    }
+}
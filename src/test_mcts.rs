@@ -1,21 +1,55 @@
 #![allow(unused_imports)]
 #![allow(dead_code)]
-use std::{sync::mpsc, thread};
+use std::{
+    ffi::{c_char, CStr, CString},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use rand::{distributions::WeightedIndex, prelude::Distribution, random, Rng};
+use rand::{distributions::WeightedIndex, prelude::Distribution, random, rngs::StdRng, Rng};
+use threadpool::ThreadPool;
 
 use crate::{
-    action::Pi,
+    action::{Action, Pi},
+    analysis::{Analysis, AnalysisSlots},
     c_array::CArray,
-    constant::{BATCH_SIZE, MOVE_LEN},
-    mcts::{MainToThread, Mcts, MctsContext, PlayerMode, ThreadToMain},
-    mcts_args::MctsArgs,
+    dedup::dedup,
+    constant::{BATCH_SIZE, MOVE_LEN, N},
+    engine_config::EngineConfig,
+    eval_cache::EvalCache,
+    example_file::{create_example_reader, ExampleReader, ExampleWriter},
+    forced_playouts,
+    evaluator::{
+        BoxedEvaluator, ChannelEvaluator, Evaluator, HeuristicEvaluator, PendingEval,
+        RolloutEvaluator, SuspendingEvaluator, UniformEvaluator,
+    },
+    mcts::{
+        Episode, MainToThread, Mcts, MctsContext, MctsInfo, PlayerMode, ResignRecord, ThreadToMain,
+        TrainExample, Turn,
+    },
+    mcts_args::{MctsArgs, RootSearch, SearchBudget, ValueTarget},
+    opponent::Opponent,
     othello_board::OthelloBoard,
+    node_action_params::Proven,
+    othello_game::{get_game_ended, get_next_state},
     player::Player,
     predict_result::PredictResult,
-    py_communicator::PyCommunicator,
-    self_player::SelfPlayer,
+    py_communicator::{
+        create_py_communicator_with_config, py_communicator_set_forced_playouts,
+        py_communicator_set_resign,
+        py_communicator_set_root_search, py_communicator_set_score_utility_weight,
+        py_communicator_set_search_budget, py_communicator_set_value_target, PyCommunicator,
+    },
+    self_player::{create_shared_search, destroy_c_string, SelfPlayConfig, SelfPlayer},
+    search_limit::SearchLimit,
+    search_task::{GameTask, SearchTask, TaskState},
+    shared_tree::SharedTree,
     thread_id::ThreadID,
+    tree_export::TreeNode,
 };
 
 //#[test]
@@ -23,6 +57,7 @@ pub fn do_test_mcts() {
     small_test();
     //thread_test();
     //commu_test()
+    //bench_evaluators();
 }
 
 pub fn small_test() {
@@ -39,12 +74,20 @@ pub fn thread_test() {
     let thread_id = ThreadID::new(0);
     thread::spawn(move || {
         
+        let evaluator = ChannelEvaluator::new(
+            send_to_main.clone(),
+            Arc::new(Mutex::new(receive_from_main)),
+            thread_id.clone(),
+            Player::PLAYER1,
+            Arc::new(EvalCache::new(0, false)),
+        );
         let mut mcts = MctsContext::new(
 			PlayerMode::_1Player,
-            send_to_main.clone(),
-            receive_from_main,
+            Box::new(evaluator),
+            None,
             thread_id.clone(),
             MctsArgs::default(),
+            AnalysisSlots::new(1),
         );
         let examples = mcts.execute_episode();
         send_to_main.send(ThreadToMain::TrainExamples(examples, thread_id))
@@ -53,16 +96,17 @@ pub fn thread_test() {
 
     let examples = loop {
         match receive_from_thread.recv().unwrap() {
-            ThreadToMain::Board(_board, _thread_id, _player, _turn) => {
+            ThreadToMain::Board(_board, _thread_id, _player) => {
                 vec.push((_board._to_string(), _player));
                 //println!("{}", board.to_string());
                 send_to_thread.send(dummy_data()).unwrap()
             }
-            ThreadToMain::TrainExamples(_examples, _thread_id) => {
+            ThreadToMain::TrainExamples(_episode, _thread_id) => {
                 //println!("done");
                 //println!("{:?}", examples);
-                break _examples;
+                break _episode.examples;
             }
+            ThreadToMain::Closed(_thread_id) => unreachable!(),
         }
     };
     for (_board_s, _p) in vec {
@@ -91,15 +135,20 @@ pub fn thread_test() {
 
 pub fn commu_test() {
     let py = PyCommunicator::new();
-    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &py.pool, &MctsArgs::default());
+    let mut sp = SelfPlayer::new(
+        PlayerMode::_1Player,
+        &py.pool,
+        &MctsArgs::default(),
+        &py.self_play,
+    );
 
     loop {
         match sp.prepare_next(0) {
             0 => {}
             1 => {
-                let _boards = sp.get_boards_for_prediction(0);
-                let (pis, win_rates) = dummy_carrays();
-                sp.receive_prediction(&pis, &win_rates, 0);
+                let boards = sp.get_boards_for_prediction(0);
+                let (pis, win_rates) = dummy_carrays(boards.size0());
+                sp.receive_prediction(&pis, &win_rates, None, 0);
             }
             2 => {
                 let hoge = sp.get_pis_for_training();
@@ -112,7 +161,7 @@ pub fn commu_test() {
 }
 
 fn dummy_data() -> MainToThread {
-    MainToThread::Prediction(dummy_data_b())
+    MainToThread::Prediction(dummy_data_b(), ThreadID::new(0))
 }
 
 fn dummy_data_b() -> PredictResult {
@@ -125,11 +174,12 @@ fn dummy_data_b() -> PredictResult {
         action_probs: Pi {
             action_probs: Box::new(vec.try_into().unwrap()),
         },
+        score: None,
     }
 }
 
-fn dummy_carrays() -> (CArray<f32>, CArray<f32>) {
-    let vec: Vec<PredictResult> = (0..BATCH_SIZE)
+fn dummy_carrays(len: usize) -> (CArray<f32>, CArray<f32>) {
+    let vec: Vec<PredictResult> = (0..len)
         .into_iter()
         .map(|_| dummy_data_b())
         .collect();
@@ -138,11 +188,1663 @@ fn dummy_carrays() -> (CArray<f32>, CArray<f32>) {
         .into_iter()
         .map(|a| (a.action_probs, a.win_rate))
         .collect();
-    let mut pis = CArray::<f32>::new2(BATCH_SIZE, MOVE_LEN);
+    let mut pis = CArray::<f32>::new2(len, MOVE_LEN);
     for (idx, item) in vec1.into_iter().enumerate() {
         pis.ref_mut2(idx).copy_from_slice(item.probs());
     }
-    let mut win_rates = CArray::<f32>::new2(BATCH_SIZE, 1);
+    let mut win_rates = CArray::<f32>::new2(len, 1);
     win_rates.as_mut().copy_from_slice(&vec2);
     (pis, win_rates)
 }
+
+/// 盤面だけから決まる予測を返す
+fn deterministic_prediction(board: &OthelloBoard) -> PredictResult {
+    let s = board.string_representation();
+    let vec: Vec<f32> = (0..MOVE_LEN)
+        .map(|i| ((s >> (i * 3 % 64)) & 0xff) as f32 + 1.0)
+        .collect();
+    PredictResult {
+        win_rate: (s % 199) as f32 / 99.0 - 1.0,
+        action_probs: Pi::new(&vec),
+        score: Some((s % 73) as f32 / 36.0 - 1.0),
+    }
+}
+
+/// 盤面だけから決まる予測を返す
+struct DeterministicEvaluator;
+
+impl Evaluator for DeterministicEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards.iter().map(deterministic_prediction).collect()
+    }
+}
+
+/// Mctsが借りる木、評価器、設定、乱数をまとめて持つ
+struct MctsFixture<E: Evaluator> {
+    info: MctsInfo,
+    evaluator: E,
+    args: MctsArgs,
+    rng: StdRng,
+}
+
+impl<E: Evaluator> MctsFixture<E> {
+    fn new(evaluator: E, args: MctsArgs, seed: u64) -> Self {
+        Self {
+            info: MctsInfo::new(),
+            evaluator,
+            args,
+            rng: ThreadID::new(0).create_rng(Some(seed)),
+        }
+    }
+
+    fn mcts(&mut self) -> Mcts<'_, E> {
+        Mcts::new(
+            &mut self.info.node_act,
+            &mut self.info.node,
+            &mut self.info.is_game_end,
+            &mut self.evaluator,
+            &mut self.args,
+            &mut self.rng,
+        )
+    }
+}
+
+fn run_episode(
+    args: MctsArgs,
+    p1_evaluator: BoxedEvaluator,
+    p2_evaluator: Option<BoxedEvaluator>,
+) -> Episode {
+    let player_mode = if p2_evaluator.is_some() {
+        PlayerMode::_2Player
+    } else {
+        PlayerMode::_1Player
+    };
+    let mut mcts = MctsContext::new(
+        player_mode,
+        p1_evaluator,
+        p2_evaluator,
+        ThreadID::new(3),
+        args,
+        AnalysisSlots::new(4),
+    );
+    mcts.execute_episode()
+}
+
+/// Pythonなしで、評価器ごとに一試合にかかる時間を測る
+pub fn bench_evaluators() {
+    let evaluators: Vec<(&str, BoxedEvaluator)> = vec![
+        ("uniform", Box::new(UniformEvaluator)),
+        ("heuristic", Box::new(HeuristicEvaluator)),
+        (
+            "rollout",
+            Box::new(RolloutEvaluator::new(8, ThreadID::new(0).create_rng(None))),
+        ),
+    ];
+    for (name, evaluator) in evaluators {
+        let start = std::time::Instant::now();
+        let episode = run_episode(MctsArgs::default(), evaluator, None);
+        println!(
+            "{name}: {} moves {:?}",
+            episode.examples.len(),
+            start.elapsed()
+        );
+    }
+}
+
+/// 共有木の探索で、スレッド数ごとに同じシミュレーション回数にかかる時間を測る
+pub fn bench_shared_search() {
+    let board = OthelloBoard::initial_board();
+    for threads in [1, 2, 4, 8] {
+        let evaluators: Vec<BoxedEvaluator> = (0..threads)
+            .map(|i| {
+                Box::new(RolloutEvaluator::new(4, ThreadID::new(i).create_rng(None)))
+                    as BoxedEvaluator
+            })
+            .collect();
+        let tree = SharedTree::new(&MctsArgs::default());
+        let start = std::time::Instant::now();
+        let analysis = tree.search_parallel(&board, Player::PLAYER1, 2000, evaluators);
+        println!(
+            "{threads} threads: pv {} {:?}",
+            analysis.pv.len(),
+            start.elapsed()
+        );
+    }
+}
+
+fn run_episode_with_deterministic_prediction(args: MctsArgs) -> Episode {
+    run_episode(args, Box::new(DeterministicEvaluator), None)
+}
+
+#[test]
+fn seeded_episode_is_reproducible() {
+    let args = MctsArgs {
+        seed: Some(12345),
+        full_search_prob: 0.5,
+        ..MctsArgs::default()
+    };
+    let episode1 = run_episode_with_deterministic_prediction(args.clone());
+    let episode2 = run_episode_with_deterministic_prediction(args.clone());
+    assert_eq!(episode1, episode2);
+
+    let args = MctsArgs {
+        root_search: RootSearch::Gumbel,
+        ..args
+    };
+    let episode1 = run_episode_with_deterministic_prediction(args.clone());
+    let episode2 = run_episode_with_deterministic_prediction(args);
+    assert_eq!(episode1, episode2);
+}
+
+#[test]
+fn episode_with_rust_evaluators() {
+    let args = MctsArgs {
+        seed: Some(1),
+        score_utility_weight: 0.5,
+        ..MctsArgs::default()
+    };
+    for (p1, p2) in [
+        (
+            Box::new(UniformEvaluator) as BoxedEvaluator,
+            Box::new(HeuristicEvaluator) as BoxedEvaluator,
+        ),
+        (
+            Box::new(RolloutEvaluator::new(4, ThreadID::new(0).create_rng(Some(1)))),
+            Box::new(DeterministicEvaluator),
+        ),
+    ] {
+        let episode = run_episode(args.clone(), p1, Some(p2));
+        let last = episode.examples.last().unwrap();
+        assert!(last.result == 1 || last.result == -1);
+        for example in &episode.examples {
+            let sum: f32 = example.pi.probs().iter().sum();
+            assert!((sum - 1.0).abs() < 1e-4);
+            //同点は先手の勝ちなので、石差が0でも勝敗はつく
+            assert!(0.0 <= example.score * example.result as f32);
+            assert!(example.score.abs() <= 1.0);
+        }
+    }
+}
+
+/// PLAYER2をopponentにして、num_mcts_sims 2の弱いプレイヤーと戦わせ、opponentの勝ち数を返す
+fn opponent_wins_against_weak_player(opponent: Opponent, games: u64) -> usize {
+    let mut wins = 0;
+    for seed in 0..games {
+        let args = MctsArgs {
+            seed: Some(seed),
+            num_mcts_sims: 2,
+            ..MctsArgs::default()
+        };
+        let mut mcts = MctsContext::new(
+            PlayerMode::_2Player,
+            Box::new(DeterministicEvaluator),
+            Some(Box::new(DeterministicEvaluator)),
+            ThreadID::new(3),
+            args,
+            AnalysisSlots::new(4),
+        );
+        opponent.install(&mut mcts, Player::PLAYER2);
+        let episode = mcts.execute_episode();
+        let last = episode.examples.last().unwrap();
+        if last.result * last.player.color() == -1 {
+            wins += 1;
+        }
+    }
+    wins
+}
+
+#[test]
+fn uct_opponent_beats_weak_player() {
+    let opponent = Opponent::Uct {
+        num_sims: 100,
+        num_rollouts: 1,
+    };
+    let wins = opponent_wins_against_weak_player(opponent, 4);
+    assert!(wins >= 3, "uct won only {} of 4", wins);
+}
+
+#[test]
+fn alpha_beta_opponent_beats_weak_player() {
+    for opponent in [
+        Opponent::AlphaBeta {
+            depth: Some(3),
+            time: None,
+        },
+        Opponent::AlphaBeta {
+            depth: None,
+            time: Some(Duration::from_millis(20)),
+        },
+    ] {
+        let wins = opponent_wins_against_weak_player(opponent, 4);
+        assert!(wins >= 3, "{:?} won only {} of 4", opponent, wins);
+    }
+}
+
+#[test]
+fn export_searched_tree() {
+    assert_eq!(Action::new(0).to_notation(), "a1");
+    assert_eq!(Action::new(N + 2).to_notation(), "c2");
+    assert_eq!(Action::new(N * N).to_notation(), "pass");
+
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, MctsArgs::default(), 0);
+    let mut mcts = fixture.mcts();
+    let board = OthelloBoard::initial_board();
+    mcts.get_action_prob(
+        &board,
+        Player::PLAYER1,
+        Turn(1),
+        1.0,
+        SearchLimit::Simulations(50),
+    );
+
+    let tree = mcts.export_tree(&board, Player::PLAYER1, 2, 0);
+    assert_eq!(tree.visits, tree.children.iter().map(|c| c.visits).sum::<usize>());
+    assert!(!tree.children.is_empty());
+    assert!(tree.children.iter().any(|c| !c.children.is_empty()));
+    assert!(tree.children.windows(2).all(|w| w[0].visits >= w[1].visits));
+
+    let shallow = mcts.export_tree(&board, Player::PLAYER1, 1, 10);
+    assert!(shallow.children.iter().all(|c| c.visits >= 10 && c.children.is_empty()));
+
+    let json = tree.to_json();
+    assert!(json.starts_with("{\"hash\":"));
+    assert!(json.contains("\"move\":\"root\""));
+    let dot = tree.to_dot();
+    assert!(dot.starts_with("digraph mcts {"));
+    assert_eq!(dot.matches("->").count(), dot.matches("[label=").count() - 1);
+
+    //Qが決まらないノードはnullにする
+    let unvisited = TreeNode {
+        q: f32::NAN,
+        children: vec![],
+        ..tree.clone()
+    };
+    assert!(unvisited.to_json().contains("\"q\":null,"));
+    destroy_c_string(std::ptr::null_mut());
+}
+
+#[test]
+fn forced_playouts_are_pruned_from_policy_target() {
+    let args = MctsArgs {
+        forced_playouts: true,
+        policy_target_pruning: true,
+        ..MctsArgs::default()
+    };
+    let mut fixture = MctsFixture::new(HeuristicEvaluator, args, 0);
+    let mut mcts = fixture.mcts();
+    //初期局面は対称で強制訪問が取り除かれないので、二手進める
+    let mut board = OthelloBoard::initial_board();
+    get_next_state(&mut board, Player::PLAYER1, Action::new(8));
+    get_next_state(&mut board, Player::PLAYER2, Action::new(7));
+    mcts.get_action_prob(
+        &board,
+        Player::PLAYER1,
+        Turn(3),
+        1.0,
+        SearchLimit::Simulations(200),
+    );
+    let s = board
+        .create_canonical_board(Player::PLAYER1)
+        .string_representation();
+    let counts = mcts.root_counts(s);
+    let node_info = &mcts.node[&s];
+    for (a, &count) in counts.iter().enumerate() {
+        if node_info.valid_moves[a] {
+            //最後のシミュレーションの後にNが1増えているので、1回分の余裕を見る
+            let forced = forced_playouts::forced_visits(2.0, node_info, a);
+            assert!(forced.floor() as usize <= count + 1);
+        }
+    }
+    let pruned = mcts.pruned_counts(s);
+    let best = (0..MOVE_LEN).max_by_key(|&a| counts[a]).unwrap();
+    assert_eq!(pruned[best], counts[best]);
+    assert!(pruned.iter().zip(&counts).all(|(p, c)| p <= c));
+    assert_ne!(pruned, counts);
+}
+
+#[test]
+fn gumbel_sequential_halving_spends_budget_on_survivors() {
+    let args = MctsArgs {
+        root_search: RootSearch::Gumbel,
+        gumbel_num_sampled_actions: 4,
+        ..MctsArgs::default()
+    };
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, args, 1);
+    let simulations = AtomicUsize::new(0);
+    let mut mcts = fixture.mcts();
+    mcts.simulations = Some(&simulations);
+    //初期局面の合法手は4つ。展開の1回を除いた16回を2フェーズで4手に2回ずつ、残った2手に4回ずつ使う
+    let board = OthelloBoard::initial_board();
+    let (action, pi) = mcts
+        .get_action_gumbel(
+            &board,
+            Player::PLAYER1,
+            Turn(1),
+            1.0,
+            SearchLimit::Simulations(17),
+        )
+        .unwrap();
+    assert_eq!(simulations.load(Ordering::Relaxed), 17);
+    let s = board
+        .create_canonical_board(Player::PLAYER1)
+        .string_representation();
+    let counts = mcts.root_counts(s);
+    assert_eq!(counts.iter().sum::<usize>(), 16);
+    let mut visited: Vec<usize> = counts.iter().copied().filter(|&c| c != 0).collect();
+    visited.sort_unstable();
+    assert_eq!(visited, vec![2, 2, 6, 6]);
+    //最後の候補は2フェーズ目まで残った手から選ばれる
+    assert_eq!(counts[action._val()], 6);
+    assert!((pi.probs().iter().sum::<f32>() - 1.0).abs() < 1e-4);
+}
+
+#[test]
+fn gumbel_without_visits_picks_best_prior() {
+    let args = MctsArgs {
+        root_search: RootSearch::Gumbel,
+        ..MctsArgs::default()
+    };
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, args, 1);
+    let mut mcts = fixture.mcts();
+    //ルートの展開だけで使い切る。temp == 0なのでノイズはなく、事前確率が最大の合法手を選ぶ
+    let board = OthelloBoard::initial_board();
+    let (action, pi) = mcts
+        .get_action_gumbel(
+            &board,
+            Player::PLAYER1,
+            Turn(1),
+            0.0,
+            SearchLimit::Simulations(1),
+        )
+        .unwrap();
+    let s = board
+        .create_canonical_board(Player::PLAYER1)
+        .string_representation();
+    let node_info = &mcts.node[&s];
+    let best = (0..MOVE_LEN)
+        .filter(|&a| node_info.valid_moves[a])
+        .max_by(|&a, &b| node_info.predicted_pi[a].total_cmp(&node_info.predicted_pi[b]))
+        .unwrap();
+    assert_eq!(action._val(), best);
+    assert!((pi.probs().iter().sum::<f32>() - 1.0).abs() < 1e-4);
+}
+
+/// playerの手番でsimsだけ探索し、ルートの証明済みの勝敗と勝ちが証明された手を返す
+fn solve(board: &OthelloBoard, player: Player, sims: usize) -> (Proven, Option<usize>) {
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, MctsArgs::default(), 0);
+    let mut mcts = fixture.mcts();
+    mcts.get_action_prob(board, player, Turn(1), 1.0, SearchLimit::Simulations(sims));
+    let s = board.create_canonical_board(player).string_representation();
+    (mcts.proven_of(s, player), mcts.proven_win_action(s))
+}
+
+/// 常に同じ予測を返す
+struct ConstantEvaluator(f32, f32);
+
+impl Evaluator for ConstantEvaluator {
+    fn evaluate(&mut self, boards: &[OthelloBoard]) -> Vec<PredictResult> {
+        boards
+            .iter()
+            .map(|_| PredictResult {
+                win_rate: self.0,
+                action_probs: Pi::new(&[1.0 / MOVE_LEN as f32; MOVE_LEN]),
+                score: Some(self.1),
+            })
+            .collect()
+    }
+}
+
+/// 一回だけsearchを呼び、手番側から見た価値を返す
+fn search_value(
+    board: &OthelloBoard,
+    player: Player,
+    evaluator: ConstantEvaluator,
+    score_utility_weight: f32,
+) -> f32 {
+    let args = MctsArgs {
+        score_utility_weight,
+        ..MctsArgs::default()
+    };
+    let mut fixture = MctsFixture::new(evaluator, args, 0);
+    -fixture.mcts().search(board, player, Turn(1))
+}
+
+#[test]
+fn score_utility_keeps_sign_and_range() {
+    //黒5つだけで終局している。黒から見て勝ちで石差は+5
+    let mut board = OthelloBoard::new();
+    for y in 0..5 {
+        board[0][y] = 1;
+    }
+    let expected = (1.0 + 5.0 / 36.0) / 2.0;
+    let v = search_value(&board, Player::PLAYER1, ConstantEvaluator(0.0, 0.0), 1.0);
+    assert!((v - expected).abs() < 1e-6, "{v}");
+    let v = search_value(&board, Player::PLAYER2, ConstantEvaluator(0.0, 0.0), 1.0);
+    assert!((v + expected).abs() < 1e-6, "{v}");
+
+    //葉の評価も手番側から見たスコアを足し、1 + 重みで割る
+    let start = OthelloBoard::initial_board();
+    let v = search_value(&start, Player::PLAYER1, ConstantEvaluator(-1.0, 0.5), 1.0);
+    assert!((v + 0.25).abs() < 1e-6, "{v}");
+    let v = search_value(&start, Player::PLAYER2, ConstantEvaluator(1.0, 1.0), 10.0);
+    assert!((v - 1.0).abs() < 1e-6, "{v}");
+    let v = search_value(&start, Player::PLAYER1, ConstantEvaluator(0.3, -1.0), 0.0);
+    assert!((v - 0.3).abs() < 1e-6, "{v}");
+}
+
+#[test]
+fn solver_proves_win_in_one() {
+    //c3(14)は白を2つとも返して終局する。a3とe3は片方しか返さない
+    let mut board = OthelloBoard::new();
+    board[0][0] = 1;
+    board[0][2] = 1;
+    board[0][4] = 1;
+    board[1][1] = -1;
+    board[1][3] = -1;
+    assert_eq!(solve(&board, Player::PLAYER1, 100), (Proven::Win, Some(2 * N + 2)));
+}
+
+#[test]
+fn solver_proves_loss_when_every_move_loses() {
+    //どちらの手も同じ白を返して終局し、4対5で負ける
+    let mut board = OthelloBoard::new();
+    board[0][0] = 1;
+    board[0][2] = 1;
+    board[1][1] = -1;
+    for y in 0..5 {
+        board[N - 1][y] = -1;
+    }
+    assert_eq!(solve(&board, Player::PLAYER1, 100), (Proven::Loss, None));
+}
+
+#[test]
+fn proven_nodes_keep_the_terminal_score() {
+    let args = MctsArgs {
+        score_utility_weight: 1.0,
+        ..MctsArgs::default()
+    };
+    //solver_proves_win_in_oneと同じ局面。c3で6対0になって終局する
+    let mut board = OthelloBoard::new();
+    board[0][0] = 1;
+    board[0][2] = 1;
+    board[0][4] = 1;
+    board[1][1] = -1;
+    board[1][3] = -1;
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, args.clone(), 0);
+    let mut mcts = fixture.mcts();
+    mcts.get_action_prob(&board, Player::PLAYER1, Turn(1), 1.0, SearchLimit::Simulations(100));
+    let win = (1.0 + 6.0 / 36.0) / 2.0;
+    assert!((mcts.root_q(&board, Player::PLAYER1) - win).abs() < 1e-6);
+    assert!((-mcts.search(&board, Player::PLAYER1, Turn(1)) - win).abs() < 1e-6);
+
+    //solver_proves_loss_when_every_move_losesと同じ局面。どちらの手も4対5で終局する
+    let mut board = OthelloBoard::new();
+    board[0][0] = 1;
+    board[0][2] = 1;
+    board[1][1] = -1;
+    for y in 0..5 {
+        board[N - 1][y] = -1;
+    }
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, args, 0);
+    let mut mcts = fixture.mcts();
+    mcts.get_action_prob(&board, Player::PLAYER1, Turn(1), 1.0, SearchLimit::Simulations(100));
+    let loss = (-1.0 - 1.0 / 36.0) / 2.0;
+    assert!((mcts.root_q(&board, Player::PLAYER1) - loss).abs() < 1e-6);
+    assert!((-mcts.search(&board, Player::PLAYER1, Turn(1)) - loss).abs() < 1e-6);
+}
+
+#[test]
+fn solver_counts_ties_for_player1() {
+    //どちらの手も4対4で終局する。get_game_endedと同じく、同点は手番によらずPlayer1の勝ち
+    let mut board = OthelloBoard::new();
+    board[0][0] = -1;
+    board[0][2] = -1;
+    board[1][1] = 1;
+    for y in 0..4 {
+        board[N - 1][y] = 1;
+    }
+    let mut after = board.clone();
+    get_next_state(&mut after, Player::PLAYER2, Action::new(2 * N + 2));
+    assert_eq!(get_game_ended(&after, Player::PLAYER1), 1);
+    assert_eq!(solve(&board, Player::PLAYER2, 100), (Proven::Loss, None));
+
+    //色を入れ替えるとcanonical boardは同じになるが、Player1の手番なので勝ち
+    let mut swapped = OthelloBoard::new();
+    for x in 0..N {
+        for y in 0..N {
+            swapped[x][y] = -board[x][y];
+        }
+    }
+    let (proven, action) = solve(&swapped, Player::PLAYER1, 100);
+    assert_eq!(proven, Proven::Win);
+    assert!(action == Some(2 * N) || action == Some(2 * N + 2));
+}
+
+#[test]
+fn policy_target_pruning_does_not_change_moves() {
+    let episode = |policy_target_pruning: bool| {
+        let args = MctsArgs {
+            num_mcts_sims: 64,
+            forced_playouts: true,
+            policy_target_pruning,
+            seed: Some(9),
+            ..MctsArgs::default()
+        };
+        run_episode(args, Box::new(HeuristicEvaluator), None)
+    };
+    let pruned = episode(true);
+    let unpruned = episode(false);
+    let boards = |episode: &Episode| -> Vec<OthelloBoard> {
+        episode
+            .examples
+            .iter()
+            .map(|e| e.canonical_board.clone())
+            .collect()
+    };
+    assert_eq!(boards(&pruned), boards(&unpruned));
+    //教師データの方策だけが変わる
+    assert!(pruned
+        .examples
+        .iter()
+        .zip(&unpruned.examples)
+        .any(|(a, b)| a.pi.probs() != b.pi.probs()));
+}
+
+fn deterministic_evaluators(threads: usize) -> Vec<BoxedEvaluator> {
+    (0..threads)
+        .map(|_| Box::new(DeterministicEvaluator) as BoxedEvaluator)
+        .collect()
+}
+
+/// ルートの手ごとの訪問回数の割合
+fn visit_distribution(analysis: &Analysis) -> Vec<f32> {
+    let total: usize = analysis.moves.iter().map(|m| m.visits).sum();
+    analysis
+        .moves
+        .iter()
+        .map(|m| m.visits as f32 / total as f32)
+        .collect()
+}
+
+#[test]
+fn shared_tree_has_no_lost_updates() {
+    let threads = 16;
+    let num_sims = 3000;
+    let tree = SharedTree::new(&MctsArgs::default());
+    let board = OthelloBoard::initial_board();
+    let analysis = tree.search_parallel(
+        &board,
+        Player::PLAYER1,
+        num_sims,
+        deterministic_evaluators(threads),
+    );
+    //ルートを展開したシミュレーションは子を訪問しない。同時に展開したスレッドの数だけ少なくなりうる
+    let root_visits: usize = analysis.moves.iter().map(|m| m.visits).sum();
+    assert!(num_sims - threads <= root_visits && root_visits < num_sims);
+    for (node_visits, child_visits, virtual_loss) in tree._consistency() {
+        assert_eq!(node_visits, child_visits);
+        assert_eq!(virtual_loss, 0);
+    }
+}
+
+#[test]
+fn shared_tree_matches_single_thread_search() {
+    let num_sims = 2000;
+    let board = OthelloBoard::initial_board();
+    let mut board2 = board.clone();
+    get_next_state(&mut board2, Player::PLAYER1, Action::new(8));
+
+    for (board, player) in [(board, Player::PLAYER1), (board2, Player::PLAYER2)] {
+        let single = SharedTree::new(&MctsArgs::default()).search_parallel(
+            &board,
+            player,
+            num_sims,
+            deterministic_evaluators(1),
+        );
+        //並列の探索は実行ごとに結果が変わるので、複数回の平均を取る
+        let parallel: Vec<Analysis> = (0..4)
+            .map(|_| {
+                SharedTree::new(&MctsArgs::default()).search_parallel(
+                    &board,
+                    player,
+                    num_sims,
+                    deterministic_evaluators(8),
+                )
+            })
+            .collect();
+
+        let mut fixture = MctsFixture::new(DeterministicEvaluator, MctsArgs::default(), 0);
+        let mut mcts = fixture.mcts();
+        mcts.get_action_prob(
+            &board,
+            player,
+            Turn(1),
+            1.0,
+            SearchLimit::Simulations(num_sims),
+        );
+        let sequential = mcts.analyze(&board, player);
+
+        let root_value = parallel.iter().map(|a| a.root_value).sum::<f32>() / 4.0;
+        assert!((root_value - single.root_value).abs() < 0.05);
+        let single = visit_distribution(&single);
+        let sequential = visit_distribution(&sequential);
+        let mut parallel_sum = vec![0.0; single.len()];
+        for analysis in &parallel {
+            for (sum, p) in parallel_sum.iter_mut().zip(visit_distribution(analysis)) {
+                *sum += p / 4.0;
+            }
+        }
+        let distance = |a: &[f32], b: &[f32]| -> f32 {
+            a.iter().zip(b).map(|(x, y)| (x - y).abs()).sum::<f32>() / 2.0
+        };
+        //一つのスレッドなら逐次のMCTSと同じ探索になる
+        assert!(distance(&single, &sequential) < 0.05);
+        //Qが拮抗する手の間では配分が揺れるので、ゆるめに比べる
+        assert!(distance(&single, &parallel_sum) < 0.2);
+    }
+}
+
+#[test]
+fn shared_tree_scores_terminal_nodes_like_single_thread_search() {
+    //どちらの手も4対4で終局する。同点は手番によらずPlayer1の勝ち
+    let mut board = OthelloBoard::new();
+    board[0][0] = 1;
+    board[0][2] = 1;
+    board[1][1] = -1;
+    for y in 0..4 {
+        board[N - 1][y] = -1;
+    }
+    for score_utility_weight in [0.0, 0.5] {
+        let args = MctsArgs {
+            score_utility_weight,
+            ..MctsArgs::default()
+        };
+        let parallel = SharedTree::new(&args).search_parallel(
+            &board,
+            Player::PLAYER1,
+            20,
+            deterministic_evaluators(2),
+        );
+        let mut fixture = MctsFixture::new(DeterministicEvaluator, args, 0);
+        let mut mcts = fixture.mcts();
+        mcts.get_action_prob(&board, Player::PLAYER1, Turn(1), 1.0, SearchLimit::Simulations(20));
+        let sequential = mcts.analyze(&board, Player::PLAYER1);
+
+        let win = 1.0 / (1.0 + score_utility_weight);
+        assert!((parallel.root_value - win).abs() < 1e-6, "{}", parallel.root_value);
+        assert!((sequential.root_value - win).abs() < 1e-6, "{}", sequential.root_value);
+        let visited = parallel.moves.iter().chain(&sequential.moves).filter(|m| 0 < m.visits);
+        for m in visited {
+            assert!((m.q - win).abs() < 1e-6, "{:?}", m);
+        }
+    }
+}
+
+/// SuspendingEvaluatorで評価のたびに中断する試合を、評価を渡しながら最後まで進める
+fn run_suspended_episode(args: MctsArgs) -> (Episode, usize) {
+    let pending = PendingEval::shared();
+    let evaluator = SuspendingEvaluator::new(
+        pending.clone(),
+        Player::PLAYER1,
+        Arc::new(EvalCache::new(0, false)),
+    );
+    let mcts = MctsContext::new(
+        PlayerMode::_1Player,
+        Box::new(evaluator),
+        None,
+        ThreadID::new(3),
+        args,
+        AnalysisSlots::new(4),
+    );
+    let mut task = GameTask::new(mcts, pending);
+    let mut suspensions = 0;
+    loop {
+        match task.resume() {
+            TaskState::Waiting(board, _player) => {
+                suspensions += 1;
+                task.receive(deterministic_prediction(&board));
+            }
+            TaskState::Finished(episode) => return (episode, suspensions),
+            TaskState::Cancelled => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn suspended_episode_matches_blocking_episode() {
+    let args = MctsArgs {
+        seed: Some(7),
+        full_search_prob: 0.5,
+        ..MctsArgs::default()
+    };
+    for root_search in [RootSearch::Puct, RootSearch::Gumbel] {
+        let args = MctsArgs {
+            root_search,
+            ..args.clone()
+        };
+        let (episode, suspensions) = run_suspended_episode(args.clone());
+        assert!(episode.examples.len() < suspensions);
+        assert_eq!(episode, run_episode_with_deterministic_prediction(args));
+    }
+}
+
+/// get_boards_for_predictionの盤面に、deterministic_predictionの予測を返す
+fn deterministic_carrays(boards: &CArray<f32>) -> (CArray<f32>, CArray<f32>) {
+    let len = boards.size0();
+    let mut pis = CArray::<f32>::new2(len, MOVE_LEN);
+    let mut win_rates = CArray::<f32>::new2(len, 1);
+    for i in 0..len {
+        let mut board = OthelloBoard::new();
+        for x in 0..N {
+            for y in 0..N {
+                board[x][y] = boards.get3(i, x, y) as i32;
+            }
+        }
+        let r = deterministic_prediction(&board);
+        pis.ref_mut2(i).copy_from_slice(r.action_probs.probs());
+        win_rates.as_mut()[i] = r.win_rate;
+    }
+    (pis, win_rates)
+}
+
+/// prepare_nextが2を返すまで、deterministic_predictionで評価する
+fn drive_self_player(sp: &mut SelfPlayer) {
+    loop {
+        match sp.prepare_next(0) {
+            0 => {}
+            1 => {
+                let boards = sp.get_boards_for_prediction(0);
+                let (pis, win_rates) = deterministic_carrays(&boards);
+                assert!(sp.receive_prediction(&pis, &win_rates, None, 0));
+            }
+            2 => return,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn receive_prediction_rejects_wrong_row_count() {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        ..MctsArgs::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &SelfPlayConfig::default());
+    assert_eq!(sp.prepare_next(0), 1);
+    let boards = sp.get_boards_for_prediction(0);
+    let (pis, win_rates) = deterministic_carrays(&boards);
+    let rows = boards.size0();
+    let short_win_rates = CArray::<f32>::new2(rows - 1, 1);
+    let long_scores = CArray::<f32>::new2(rows + 1, 1);
+    assert!(!sp.receive_prediction(&pis, &short_win_rates, None, 0));
+    assert!(!sp.receive_prediction(&pis, &win_rates, Some(&long_scores), 0));
+    //失敗してもバッチは残っていて、正しい予測を渡し直せる
+    assert!(sp.receive_prediction(&pis, &win_rates, None, 0));
+    drive_self_player(&mut sp);
+    assert!(0 < sp.get_boards_for_training().size0());
+}
+
+#[test]
+fn in_flight_counts_only_running_games() {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig {
+        concurrent_games: 4,
+        games_per_generation: 6,
+        ..SelfPlayConfig::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &config);
+    loop {
+        let stats = sp.get_stats();
+        let (games, in_flight) = (stats.as_ref()[0], stats.as_ref()[1]);
+        //終わった試合と進行中の試合の合計は、一世代の試合数を超えない
+        assert!(in_flight <= 4.0 && games + in_flight <= 6.0, "{games} {in_flight}");
+        match sp.prepare_next(0) {
+            0 => {}
+            1 => {
+                let boards = sp.get_boards_for_prediction(0);
+                let (pis, win_rates) = deterministic_carrays(&boards);
+                assert!(sp.receive_prediction(&pis, &win_rates, None, 0));
+            }
+            2 => break,
+            _ => unreachable!(),
+        }
+    }
+    let stats = sp.get_stats();
+    assert_eq!(stats.as_ref()[0], 6.0);
+    assert_eq!(stats.as_ref()[1], 0.0);
+}
+
+#[test]
+fn self_player_multiplexes_games_over_few_threads() {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig::default();
+    let mut sp = SelfPlayer::new(PlayerMode::_2Player, &pool, &args, &config);
+    drive_self_player(&mut sp);
+    let results = sp.get_results_for_counting();
+    assert_eq!(results.as_ref().len(), BATCH_SIZE);
+    assert!(results.as_ref().iter().all(|&r| r == 1.0 || r == -1.0));
+
+    let num_sims = 500;
+    let mut sp = SelfPlayer::shared_search(
+        &pool,
+        &args,
+        &config,
+        OthelloBoard::initial_board(),
+        Player::PLAYER1,
+        num_sims,
+    );
+    drive_self_player(&mut sp);
+    let visits = sp.get_analysis_moves(0).unwrap();
+    let root_visits: f32 = (0..visits.size0()).map(|i| visits.get2(i, 1)).sum();
+    assert!((num_sims - BATCH_SIZE) as f32 <= root_visits && root_visits < num_sims as f32);
+}
+
+#[test]
+fn self_player_refills_slots_until_generation_is_done() {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        seed: Some(3),
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig {
+        concurrent_games: 8,
+        batch_size: 3,
+        games_per_generation: 20,
+        ..SelfPlayConfig::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &config);
+    let mut positions = 0;
+    let mut batches = 0;
+    loop {
+        match sp.prepare_next(0) {
+            0 => {}
+            1 => {
+                let boards = sp.get_boards_for_prediction(0);
+                assert!(0 < boards.size0() && boards.size0() <= config.batch_size);
+                positions += boards.size0();
+                batches += 1;
+                let (pis, win_rates) = deterministic_carrays(&boards);
+                sp.receive_prediction(&pis, &win_rates, None, 0);
+            }
+            2 => break,
+            _ => unreachable!(),
+        }
+    }
+    let results = sp.get_results_for_counting();
+    assert_eq!(results.as_ref().len(), config.games_per_generation);
+
+    let stats = sp.get_stats();
+    let [games, in_flight, ply, evaluated, requests, _hits, sims_per_sec, fill] =
+        stats.as_ref().try_into().unwrap();
+    assert_eq!(games as usize, config.games_per_generation);
+    assert_eq!(in_flight, 0.0);
+    assert!((9.0..=100.0).contains(&ply), "{ply}");
+    assert_eq!(evaluated as usize, positions);
+    assert_eq!(requests as usize, batches);
+    assert!(0.0 < sims_per_sec);
+    assert_eq!(fill, positions as f32 / (batches * config.batch_size) as f32);
+}
+
+/// 自己対戦を最後まで進め、学習用の盤面を並べ替えて返す。各バッチの行とthread_idが対応しているか確かめる
+fn sorted_training_boards(config: &SelfPlayConfig) -> Vec<Vec<i32>> {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        seed: Some(5),
+        ..MctsArgs::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, config);
+    loop {
+        match sp.prepare_next(0) {
+            0 => {}
+            1 => {
+                let boards = sp.get_boards_for_prediction(0);
+                let ids = sp.get_batch_thread_ids();
+                assert_eq!(ids.size0(), boards.size0());
+                let mut ids: Vec<usize> = ids.as_ref().iter().map(|&id| id as usize).collect();
+                ids.sort();
+                ids.dedup();
+                assert_eq!(ids.len(), boards.size0());
+                assert!(ids.iter().all(|&id| id < config.concurrent_games));
+                let (pis, win_rates) = deterministic_carrays(&boards);
+                sp.receive_prediction(&pis, &win_rates, None, 0);
+            }
+            2 => break,
+            _ => unreachable!(),
+        }
+    }
+    let boards = sp.get_boards_for_training();
+    let mut rows: Vec<Vec<i32>> = (0..boards.size0())
+        .map(|i| boards.ref3_1(i).iter().map(|&c| c as i32).collect())
+        .collect();
+    rows.sort();
+    rows
+}
+
+#[test]
+fn partial_batches_give_same_games() {
+    let full = SelfPlayConfig {
+        concurrent_games: 6,
+        batch_size: 6,
+        games_per_generation: 6,
+        min_batch_size: 6,
+        batch_timeout: Duration::from_secs(1),
+    };
+    let partial = SelfPlayConfig {
+        batch_size: 4,
+        min_batch_size: 2,
+        batch_timeout: Duration::from_micros(100),
+        ..full
+    };
+    assert_eq!(sorted_training_boards(&full), sorted_training_boards(&partial));
+}
+
+#[test]
+fn continuous_self_play_fills_replay_buffer() {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        seed: Some(9),
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig {
+        concurrent_games: 4,
+        games_per_generation: 1,
+        ..SelfPlayConfig::default()
+    };
+    let capacity = 50;
+    let mut sp = SelfPlayer::continuous(PlayerMode::_1Player, &pool, &args, &config, capacity);
+    //games_per_generationを過ぎても試合を続ける
+    let stats = loop {
+        let stats = sp.replay_buffer().unwrap().get_stats();
+        if 6.0 <= stats.get1(1) {
+            break stats;
+        }
+        assert_eq!(sp.prepare_next(0), 1);
+        let boards = sp.get_boards_for_prediction(0);
+        let (pis, win_rates) = deterministic_carrays(&boards);
+        sp.receive_prediction(&pis, &win_rates, None, 0);
+    };
+    assert_eq!(stats.get1(0), capacity as f32);
+    assert!(capacity as f32 <= stats.get1(2));
+
+    let buffer = sp.replay_buffer().unwrap();
+    assert_eq!(buffer.sample(20), 20);
+    assert_eq!(buffer.sampled().get_boards().size0(), 20);
+    assert_eq!(buffer.sampled().get_pis().size0(), 20);
+    assert!(buffer.sampled().get_players().as_ref().iter().all(|&p| p == 1.0 || p == -1.0));
+    assert_eq!(buffer.sample(1000), capacity);
+}
+
+#[test]
+fn example_file_round_trip() {
+    let path = std::env::temp_dir().join(format!("train_examples_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig {
+        concurrent_games: 4,
+        games_per_generation: 4,
+        ..SelfPlayConfig::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &config);
+    sp.write_examples_to(&path, 16).unwrap();
+    drive_self_player(&mut sp);
+    let expected = sp.get_boards_for_training().size0();
+    let pis = sp.get_pis_for_training();
+    //prepare_nextが2を返した時点で最後のチャンクまで書かれている
+    assert_eq!(ExampleReader::open(&[&path], 0, 0).unwrap().len(), expected);
+    assert_eq!(sp.example_write_error(), None);
+    drop(sp);
+
+    //書きかけのチャンクは読まず、次に書くときに切り詰める
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    std::io::Write::write_all(&mut file, &[5, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+    let reader = ExampleReader::open(&[&path], 0, 0).unwrap();
+    assert_eq!(reader.len(), expected);
+    drop(ExampleWriter::open(&path, 16).unwrap());
+
+    let mut reader = ExampleReader::open(&[&path], 0, 0).unwrap();
+    assert_eq!(reader.next_batch(expected).unwrap(), expected);
+    let read_pis = reader.batch().get_pis();
+    for (a, b) in pis.as_ref().iter().zip(read_pis.as_ref()) {
+        assert!((a - b).abs() < 1e-4);
+    }
+    let first = reader.batch().examples.clone();
+
+    //シャッフルしても同じものを一度ずつ読む
+    let mut reader = ExampleReader::open(&[&path], 2, 1).unwrap();
+    let mut shuffled = vec![];
+    while 0 < reader.next_batch(10).unwrap() {
+        shuffled.extend(reader.batch().examples.iter().cloned());
+    }
+    assert_eq!(shuffled.len(), expected);
+    assert_ne!(shuffled, first);
+    let key = |e: &TrainExample| format!("{:?} {:?}", e.canonical_board, e.pi);
+    let mut a: Vec<String> = first.iter().map(key).collect();
+    let mut b: Vec<String> = shuffled.iter().map(key).collect();
+    a.sort();
+    b.sort();
+    assert_eq!(a, b);
+
+    assert!(ExampleReader::open(&[std::env::temp_dir()], 0, 0).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fast_search_moves_are_not_exported() {
+    let path = std::env::temp_dir().join(format!("full_search_examples_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        full_search_prob: 0.5,
+        seed: Some(3),
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig {
+        concurrent_games: 4,
+        games_per_generation: 4,
+        ..SelfPlayConfig::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &config);
+    sp.write_examples_to(&path, 16).unwrap();
+    drive_self_player(&mut sp);
+    let exported = sp.get_pis_for_training().size0();
+    assert_eq!(sp.get_boards_for_training().size0(), exported);
+    assert_eq!(sp.get_value_targets_for_training().size0(), exported);
+    let stats = sp.get_stats();
+    let plies = (stats.as_ref()[0] * stats.as_ref()[2]).round() as usize;
+    assert!(0 < exported && exported < plies, "{exported} of {plies}");
+    drop(sp);
+
+    let mut reader = ExampleReader::open(&[&path], 0, 0).unwrap();
+    assert_eq!(reader.next_batch(plies).unwrap(), exported);
+    std::fs::remove_file(&path).unwrap();
+
+    //書き出すのはフルサーチした手だけで、その順番どおり
+    let episode = run_episode_with_deterministic_prediction(args);
+    let full: Vec<&TrainExample> = episode.examples.iter().filter(|e| e.is_full_search).collect();
+    assert!(!full.is_empty() && full.len() < episode.examples.len());
+    let mut writer = ExampleWriter::open(&path, 16).unwrap();
+    writer.write_game(&episode.examples).unwrap();
+    drop(writer);
+    let mut reader = ExampleReader::open(&[&path], 0, 0).unwrap();
+    assert_eq!(reader.next_batch(episode.examples.len()).unwrap(), full.len());
+    for (read, e) in reader.batch().examples.iter().zip(&full) {
+        assert_eq!(read.canonical_board, e.canonical_board);
+        assert_eq!(read.player, e.player);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+fn example_with(board: OthelloBoard, pi: Pi, value_target: f32) -> TrainExample {
+    TrainExample {
+        pi,
+        canonical_board: board,
+        player: Player::PLAYER1,
+        result: 1,
+        _turn: Turn(0),
+        is_full_search: true,
+        _q: 0.0,
+        value_target,
+        score: 0.0,
+    }
+}
+
+#[test]
+fn dedup_averages_targets() {
+    let mut board = OthelloBoard::new();
+    board[0][1] = 1;
+    board[2][3] = -1;
+    //転置した盤面と方策
+    let mut transposed = OthelloBoard::new();
+    let mut one_hot = vec![0.0; MOVE_LEN];
+    let mut transposed_one_hot = vec![0.0; MOVE_LEN];
+    for x in 0..N {
+        for y in 0..N {
+            transposed[y][x] = board[x][y];
+        }
+    }
+    one_hot[1] = 1.0;
+    transposed_one_hot[N] = 1.0;
+    let mut pass = vec![0.0; MOVE_LEN];
+    pass[N * N] = 1.0;
+    let examples = vec![
+        example_with(board.clone(), Pi::new(&one_hot), 1.0),
+        example_with(board.clone(), Pi::new(&pass), 0.0),
+        example_with(transposed, Pi::new(&transposed_one_hot), 0.5),
+    ];
+
+    let deduped = dedup(&examples, false);
+    assert_eq!(deduped.counts, vec![2, 1]);
+    assert_eq!(deduped.examples[0].value_target, 0.5);
+    assert_eq!(deduped.examples[0].pi.probs()[1], 0.5);
+    assert_eq!(deduped.examples[0].pi.probs()[N * N], 0.5);
+
+    let deduped = dedup(&examples, true);
+    assert_eq!(deduped.counts, vec![3]);
+    assert!((deduped.examples[0].value_target - 0.5).abs() < 1e-6);
+    //向きをそろえてから平均するので、同じ手に集まる
+    let probs = deduped.examples[0].pi.probs();
+    assert!(probs.iter().any(|&p| (p - 2.0 / 3.0).abs() < 1e-6));
+    assert_eq!(deduped.get_weights().as_ref(), &[3.0]);
+
+    //同じ世代の中と、ファイルから読んだものでまとめた結果は一致する
+    let path = std::env::temp_dir().join(format!("dedup_examples_{}.bin", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        ..MctsArgs::default()
+    };
+    let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &SelfPlayConfig::default());
+    sp.write_examples_to(&path, 100).unwrap();
+    drive_self_player(&mut sp);
+    let len = sp.get_weights_for_training().size0();
+    let unique = sp.dedup_train_examples(true);
+    assert!(unique < len);
+    let weights = sp.get_weights_for_training();
+    assert_eq!(weights.as_ref().iter().sum::<f32>(), len as f32);
+    assert_eq!(sp.get_boards_for_training().size0(), unique);
+    let values = sp.get_value_targets_for_training();
+    drop(sp);
+
+    let mut reader = ExampleReader::open(&[&path], 0, 0).unwrap();
+    assert_eq!(reader.dedup_all(true).unwrap(), unique);
+    assert_eq!(reader.batch().get_weights().as_ref(), weights.as_ref());
+    assert_eq!(reader.batch().get_value_targets().as_ref(), values.as_ref());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn engine_config_from_json_and_toml() {
+    let json = r#"{
+        "num_threads": 3,
+        "mcts": {"num_mcts_sims": 100, "cpuct": 1.5, "temp_threshold": 8,
+                 "root_search": "gumbel", "seed": 42,
+                 "search_budget": {"game_clock": {"main_time": 60, "increment": 0.5}}}
+    }"#;
+    let config = EngineConfig::parse(json).unwrap();
+    assert_eq!(config.num_threads(), 3);
+    assert_eq!(config.mcts.num_mcts_sims, 100);
+    assert_eq!(config.mcts.cpuct, 1.5);
+    assert_eq!(config.mcts.temp_threshold, 8);
+    assert_eq!(config.mcts.root_search, RootSearch::Gumbel);
+    assert_eq!(config.mcts.seed, Some(42));
+    assert_eq!(
+        config.mcts.search_budget,
+        SearchBudget::GameClock {
+            main_time: Duration::from_secs(60),
+            increment: Duration::from_millis(500),
+        }
+    );
+    //書かなかったものはデフォルト
+    assert_eq!(config.mcts.fast_num_mcts_sims, MctsArgs::default().fast_num_mcts_sims);
+
+    assert_eq!(config.self_play(), SelfPlayConfig::default());
+
+    let toml = r#"
+        concurrent_games = 256
+        batch_size = 32
+        games_per_generation = 1000
+        min_batch_size = 16
+        batch_timeout_us = 250
+        [mcts]
+        value_target = "interpolate"
+        search_budget = { time_per_move = 0.25 }
+    "#;
+    let config = EngineConfig::parse(toml).unwrap();
+    assert_eq!(
+        config.self_play(),
+        SelfPlayConfig {
+            concurrent_games: 256,
+            batch_size: 32,
+            games_per_generation: 1000,
+            min_batch_size: 16,
+            batch_timeout: Duration::from_micros(250),
+        }
+    );
+    assert_eq!(config.mcts.value_target, ValueTarget::Interpolate);
+    assert_eq!(
+        config.mcts.search_budget,
+        SearchBudget::TimePerMove(Duration::from_millis(250))
+    );
+
+    for (text, expected) in [
+        (r#"{"mcts": {"num_mcts_simz": 10}}"#, "num_mcts_simz"),
+        ("threads = 4", "threads"),
+        (r#"{"mcts": {"root_search": "alphabeta"}}"#, "alphabeta"),
+        (r#"{"mcts": {"cpuct": -1.0}}"#, "cpuct"),
+        ("[mcts]\nfull_search_prob = 1.5", "full_search_prob"),
+        ("[mcts]\nsearch_budget = { time_per_move = -1.0 }", "time_per_move"),
+        (r#"{"num_threads": 0}"#, "num_threads"),
+        ("batch_size = 0", "batch_size"),
+        ("min_batch_size = 0", "min_batch_size"),
+    ] {
+        let error = EngineConfig::parse(text).unwrap_err();
+        assert!(error.contains(expected), "{error}");
+    }
+}
+
+#[test]
+fn ffi_constructors_report_errors_safely() {
+    //エラーメッセージにNUL文字が入っても、errorがNULL POINTERでもパニックしない
+    let config = CString::new(r#"{"mcts": {"x\u0000y": 1}}"#).unwrap();
+    let mut error: *mut c_char = std::ptr::null_mut();
+    assert!(create_py_communicator_with_config(config.as_ptr(), &mut error).is_null());
+    let message = unsafe { CStr::from_ptr(error) }.to_str().unwrap().to_string();
+    destroy_c_string(error);
+    assert!(message.contains("x\u{FFFD}y"), "{message}");
+    assert!(create_py_communicator_with_config(config.as_ptr(), std::ptr::null_mut()).is_null());
+
+    let paths = CString::new(std::env::temp_dir().to_str().unwrap()).unwrap();
+    assert!(create_example_reader(paths.as_ptr(), 0, 0, std::ptr::null_mut()).is_null());
+}
+
+#[test]
+fn create_shared_search_checks_board_shape() {
+    let mut py = PyCommunicator::new();
+    let mut board = CArray::<f32>::new2(N, N);
+    board.set2(2, 2, 1.0);
+    board.set2(3, 3, 1.0);
+    board.set2(2, 3, -1.0);
+    board.set2(3, 2, -1.0);
+    let sp = create_shared_search(&mut py, &board, 1, 10);
+    assert!(!sp.is_null());
+    drop(unsafe { Box::from_raw(sp) });
+
+    assert!(create_shared_search(&mut py, &board, 0, 10).is_null());
+    assert!(create_shared_search(&mut py, &CArray::<f32>::new2(N, N + 1), 1, 10).is_null());
+    assert!(create_shared_search(&mut py, &CArray::<f32>::new1(N * N), 1, 10).is_null());
+    assert!(create_shared_search(&mut py, std::ptr::null(), 1, 10).is_null());
+    board.set2(0, 0, 2.0);
+    assert!(create_shared_search(&mut py, &board, 1, 10).is_null());
+}
+
+#[test]
+fn uct_opponent_ignores_learner_settings() {
+    let args = MctsArgs {
+        root_search: RootSearch::Gumbel,
+        forced_playouts: true,
+        score_utility_weight: 1.0,
+        resign_threshold: 0.5,
+        early_stop: true,
+        full_search_prob: 0.25,
+        search_budget: SearchBudget::RootVisits(100),
+        ..MctsArgs::default()
+    };
+    let mut mcts = MctsContext::new(
+        PlayerMode::_2Player,
+        Box::new(UniformEvaluator),
+        Some(Box::new(UniformEvaluator)),
+        ThreadID::new(0),
+        args,
+        AnalysisSlots::new(1),
+    );
+    Opponent::Uct {
+        num_sims: 50,
+        num_rollouts: 1,
+    }
+    .install(&mut mcts, Player::PLAYER2);
+    let uct = mcts.p2_args.as_ref().unwrap();
+    let default = MctsArgs::default();
+    assert_eq!(uct.num_mcts_sims, 50);
+    assert_eq!(uct.root_search, RootSearch::Puct);
+    assert_eq!(uct.search_budget, SearchBudget::Simulations);
+    assert_eq!(uct.forced_playouts, default.forced_playouts);
+    assert_eq!(uct.score_utility_weight, default.score_utility_weight);
+    assert_eq!(uct.resign_threshold, default.resign_threshold);
+    assert_eq!(uct.early_stop, default.early_stop);
+    assert_eq!(uct.full_search_prob, default.full_search_prob);
+}
+
+#[test]
+fn cancelled_self_players_release_pool_threads() {
+    let pool = ThreadPool::new(2);
+    let args = MctsArgs {
+        num_mcts_sims: 8,
+        seed: Some(13),
+        ..MctsArgs::default()
+    };
+    let config = SelfPlayConfig {
+        concurrent_games: 4,
+        batch_size: 4,
+        ..SelfPlayConfig::default()
+    };
+    //先手の相手は、中止されなければ終わらないほど長く考える
+    let opponents = [
+        None,
+        Some(Opponent::Uct {
+            num_sims: 100_000_000,
+            num_rollouts: 1,
+        }),
+        Some(Opponent::AlphaBeta {
+            depth: None,
+            time: Some(Duration::from_secs(600)),
+        }),
+    ];
+    for cycle in 0..12 {
+        let opponent = opponents[cycle % opponents.len()];
+        let player_mode = if opponent.is_some() {
+            PlayerMode::_2Player
+        } else {
+            PlayerMode::_1Player
+        };
+        let mut sp = SelfPlayer::with_opponent(
+            player_mode,
+            &pool,
+            &args,
+            &config,
+            opponent.map(|opponent| (Player::PLAYER1, opponent)),
+        );
+        if opponent.is_some() {
+            //全ワーカーが相手の探索に入るのを待つ。prepare_nextは盤面が来ないので呼べない
+            thread::sleep(Duration::from_millis(20));
+        } else {
+            for _ in 0..cycle % 4 {
+                if sp.prepare_next(0) != 1 {
+                    break;
+                }
+                let boards = sp.get_boards_for_prediction(0);
+                let (pis, win_rates) = deterministic_carrays(&boards);
+                sp.receive_prediction(&pis, &win_rates, None, 0);
+            }
+        }
+        //半分は明示的に中止し、残りはそのまま捨てる
+        if cycle % 2 == 0 {
+            sp.cancel();
+            assert_eq!(sp.prepare_next(0), 2);
+            //終わった試合がなくても教師データは取り出せる
+            let len = sp.get_boards_for_training().size0();
+            assert_eq!(sp.get_pis_for_training().size0(), len);
+            assert_eq!(sp.get_value_targets_for_training().size0(), len);
+            assert_eq!(sp.get_weights_for_training().size0(), len);
+            assert_eq!(sp.dedup_train_examples(false), len);
+            assert_eq!(sp.get_resign_stats().as_ref()[0], 0.0);
+            assert_eq!(sp.get_results_for_counting().size0(), 0);
+        }
+    }
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while 0 < pool.active_count() {
+        assert!(Instant::now() < deadline, "{} threads still busy", pool.active_count());
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(pool.panic_count(), 0);
+}
+
+#[test]
+fn value_target_blends_z_and_q() {
+    let (z, q) = (1.0, -0.5);
+    for (ply, interpolated) in [(1, -0.35), (5, 0.25), (10, 1.0)] {
+        assert_eq!(ValueTarget::Z.mix(z, q, ply, 10), z);
+        assert_eq!(ValueTarget::Q.mix(z, q, ply, 10), q);
+        assert_eq!(ValueTarget::Average.mix(z, q, ply, 10), 0.25);
+        let mixed = ValueTarget::Interpolate.mix(z, q, ply, 10);
+        assert!((mixed - interpolated).abs() < 1e-6, "ply {ply}: {mixed}");
+    }
+
+    let mut py = PyCommunicator::new();
+    assert!(py_communicator_set_value_target(&mut py, 3));
+    assert_eq!(py.mcts_args.value_target, ValueTarget::Interpolate);
+    assert!(!py_communicator_set_value_target(&mut py, 4));
+    assert_eq!(py.mcts_args.value_target, ValueTarget::Interpolate);
+}
+
+#[test]
+fn root_search_setter_rejects_unknown_kind() {
+    let mut py = PyCommunicator::new();
+    assert!(py_communicator_set_root_search(&mut py, 1));
+    assert_eq!(py.mcts_args.root_search, RootSearch::Gumbel);
+    assert!(!py_communicator_set_root_search(&mut py, 2));
+    assert_eq!(py.mcts_args.root_search, RootSearch::Gumbel);
+}
+
+#[test]
+fn search_budget_setter_rejects_invalid_input() {
+    let mut py = PyCommunicator::new();
+    assert!(py_communicator_set_search_budget(&mut py, 3, 60.0, 0.5));
+    let budget = SearchBudget::GameClock {
+        main_time: Duration::from_secs(60),
+        increment: Duration::from_millis(500),
+    };
+    assert_eq!(py.mcts_args.search_budget, budget);
+    for (kind, value, increment) in [
+        (4, 1.0, 0.0),
+        (1, -1.0, 0.0),
+        (1, f64::NAN, 0.0),
+        (1, f64::INFINITY, 0.0),
+        (2, 1.5, 0.0),
+        (2, -1.0, 0.0),
+        (2, 1e30, 0.0),
+        (3, 60.0, f64::NAN),
+    ] {
+        assert!(!py_communicator_set_search_budget(&mut py, kind, value, increment));
+        assert_eq!(py.mcts_args.search_budget, budget);
+    }
+    assert!(py_communicator_set_search_budget(&mut py, 2, 800.0, 0.0));
+    assert_eq!(py.mcts_args.search_budget, SearchBudget::RootVisits(800));
+    assert!(py_communicator_set_search_budget(&mut py, 0, f64::NAN, 0.0));
+    assert_eq!(py.mcts_args.search_budget, SearchBudget::Simulations);
+}
+
+#[test]
+fn mcts_args_setters_validate_like_config() {
+    let mut py = PyCommunicator::new();
+    let defaults = py.mcts_args.clone();
+    assert!(!py_communicator_set_resign(&mut py, f32::NAN, 0.1));
+    assert!(!py_communicator_set_resign(&mut py, 0.9, 1.5));
+    assert!(!py_communicator_set_forced_playouts(&mut py, true, -2.0, true));
+    assert!(!py_communicator_set_score_utility_weight(&mut py, -0.5));
+    assert!(!py_communicator_set_score_utility_weight(&mut py, f32::NAN));
+    assert_eq!(py.mcts_args.resign_threshold, defaults.resign_threshold);
+    assert_eq!(py.mcts_args.resign_disabled_fraction, defaults.resign_disabled_fraction);
+    assert_eq!(py.mcts_args.forced_playouts, defaults.forced_playouts);
+    assert_eq!(py.mcts_args.score_utility_weight, defaults.score_utility_weight);
+
+    assert!(py_communicator_set_resign(&mut py, -0.9, 0.1));
+    assert!(py_communicator_set_forced_playouts(&mut py, true, 3.0, false));
+    assert!(py_communicator_set_score_utility_weight(&mut py, 0.5));
+    assert_eq!(py.mcts_args.resign_threshold, -0.9);
+    assert_eq!(py.mcts_args.forced_playouts_k, 3.0);
+    assert_eq!(py.mcts_args.score_utility_weight, 0.5);
+}
+
+#[test]
+fn root_visits_budget_counts_reused_visits() {
+    let mut fixture = MctsFixture::new(DeterministicEvaluator, MctsArgs::default(), 0);
+    let mut mcts = fixture.mcts();
+    let board = OthelloBoard::initial_board();
+    let s = board
+        .create_canonical_board(Player::PLAYER1)
+        .string_representation();
+    for (limit, visits) in [
+        (SearchLimit::Simulations(31), 30),
+        (SearchLimit::RootVisits(50), 50),
+        //すでに足りていれば探索しない
+        (SearchLimit::RootVisits(20), 50),
+    ] {
+        mcts.get_action_prob(&board, Player::PLAYER1, Turn(1), 1.0, limit);
+        assert_eq!(mcts.root_counts(s).iter().sum::<usize>(), visits);
+    }
+}
+
+/// 初期局面からlimitで探索し、シミュレーションの数とルートの訪問回数を返す
+fn simulations_until(args: MctsArgs, limit: SearchLimit) -> (usize, Vec<usize>) {
+    let mut fixture = MctsFixture::new(HeuristicEvaluator, args, 0);
+    let simulations = AtomicUsize::new(0);
+    let mut mcts = fixture.mcts();
+    mcts.simulations = Some(&simulations);
+    let mut board = OthelloBoard::initial_board();
+    get_next_state(&mut board, Player::PLAYER1, Action::new(8));
+    mcts.decide_move(&board, Player::PLAYER2, Turn(2), 1.0, limit);
+    let s = board
+        .create_canonical_board(Player::PLAYER2)
+        .string_representation();
+    (simulations.load(Ordering::Relaxed), mcts.root_counts(s))
+}
+
+#[test]
+fn early_stop_ends_search_once_best_move_is_decided() {
+    let limit = SearchLimit::Simulations(400);
+    let (sims, _) = simulations_until(MctsArgs::default(), limit);
+    assert_eq!(sims, 400);
+
+    let args = MctsArgs {
+        early_stop: true,
+        ..MctsArgs::default()
+    };
+    let (sims, mut counts) = simulations_until(args, limit);
+    assert!(sims < 400, "{sims}");
+    //残りを全部2番目の手に使っても追い越せない
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    assert!(counts[1] + (400 - sims) < counts[0], "{counts:?} {sims}");
+}
+
+#[test]
+fn gumbel_stops_at_deadline() {
+    let args = MctsArgs {
+        root_search: RootSearch::Gumbel,
+        num_mcts_sims: 1_000_000,
+        ..MctsArgs::default()
+    };
+    let start = Instant::now();
+    let limit = SearchLimit::Deadline(start + Duration::from_millis(50));
+    let (sims, counts) = simulations_until(args, limit);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert!(0 < sims && sims < 1_000_000);
+    assert!(counts.iter().any(|&c| c != 0));
+}
+
+#[test]
+fn resignation_is_recorded_with_control_games() {
+    //閾値が高いので、初手のPlayer1は必ず投了したくなる
+    let args = MctsArgs {
+        num_mcts_sims: 16,
+        resign_threshold: 0.99,
+        resign_disabled_fraction: 0.0,
+        seed: Some(17),
+        ..MctsArgs::default()
+    };
+    let episode = run_episode(args.clone(), Box::new(HeuristicEvaluator), None);
+    assert_eq!(episode.resign, ResignRecord::Resigned);
+    assert_eq!(episode.examples.len(), 1);
+    assert_eq!(episode.examples[0].player, Player::PLAYER1);
+    assert_eq!(episode.examples[0].result, -1);
+
+    //投了を禁止すると最後まで指し、投了していたら本当に負けていたかを記録する
+    for seed in 0..4 {
+        let args = MctsArgs {
+            resign_disabled_fraction: 1.0,
+            seed: Some(seed),
+            ..args.clone()
+        };
+        let episode = run_episode(args, Box::new(HeuristicEvaluator), None);
+        let first = &episode.examples[0];
+        assert_eq!(first.player, Player::PLAYER1);
+        assert!(1 < episode.examples.len());
+        for example in &episode.examples {
+            let same_side = example.player == first.player;
+            assert_eq!(example.result, if same_side { first.result } else { -first.result });
+        }
+        assert_eq!(
+            episode.resign,
+            ResignRecord::Control {
+                would_have_lost: first.result < 0
+            }
+        );
+    }
+
+    //SelfPlayerの集計
+    let pool = ThreadPool::new(2);
+    let config = SelfPlayConfig {
+        concurrent_games: 4,
+        games_per_generation: 4,
+        ..SelfPlayConfig::default()
+    };
+    for (disabled_fraction, resigned, control) in [(0.0, 4.0, 0.0), (1.0, 0.0, 4.0)] {
+        let args = MctsArgs {
+            num_mcts_sims: 8,
+            resign_threshold: 0.99,
+            resign_disabled_fraction: disabled_fraction,
+            seed: Some(3),
+            ..MctsArgs::default()
+        };
+        let mut sp = SelfPlayer::new(PlayerMode::_1Player, &pool, &args, &config);
+        drive_self_player(&mut sp);
+        let stats = sp.get_resign_stats();
+        let [r, c, false_positives, rate] = stats.as_ref().try_into().unwrap();
+        assert_eq!((r, c), (resigned, control));
+        assert!(false_positives <= c);
+        assert_eq!(rate, if c == 0.0 { 0.0 } else { false_positives / c });
+    }
+}
+
+#[test]
+fn eval_cache_counts_evicts_and_separates_players() {
+    let prediction = |win_rate: f32| PredictResult {
+        win_rate,
+        action_probs: Pi::new(&[1.0; MOVE_LEN]),
+        score: None,
+    };
+    let win_rate = |r: Option<PredictResult>| r.map(|r| r.win_rate);
+
+    //16の倍数のハッシュは同じシャードに入る。シャードあたり2つまで
+    let cache = EvalCache::new(32, false);
+    assert_eq!(win_rate(cache.get(0, Player::PLAYER1)), None);
+    cache.insert(0, Player::PLAYER1, prediction(0.1));
+    cache.insert(16, Player::PLAYER1, prediction(0.2));
+    //入れ直しても順番は変わらない
+    cache.insert(0, Player::PLAYER1, prediction(0.1));
+    assert_eq!(win_rate(cache.get(0, Player::PLAYER2)), Some(0.1));
+    cache.insert(32, Player::PLAYER1, prediction(0.3));
+    assert_eq!(win_rate(cache.get(0, Player::PLAYER1)), None);
+    assert_eq!(win_rate(cache.get(16, Player::PLAYER1)), Some(0.2));
+    assert_eq!(win_rate(cache.get(32, Player::PLAYER1)), Some(0.3));
+    //別のシャードには影響しない
+    cache.insert(1, Player::PLAYER1, prediction(0.4));
+    assert_eq!(win_rate(cache.get(16, Player::PLAYER1)), Some(0.2));
+    assert_eq!((cache.hits(), cache.misses()), (4, 2));
+
+    cache.clear();
+    assert_eq!(win_rate(cache.get(16, Player::PLAYER1)), None);
+    assert_eq!(win_rate(cache.get(1, Player::PLAYER1)), None);
+
+    //NNの重みの版が変わったときだけ捨てる
+    cache.insert(2, Player::PLAYER1, prediction(0.6));
+    cache.set_network_version(0);
+    assert_eq!(win_rate(cache.get(2, Player::PLAYER1)), Some(0.6));
+    cache.set_network_version(1);
+    assert_eq!(win_rate(cache.get(2, Player::PLAYER1)), None);
+    cache.insert(2, Player::PLAYER1, prediction(0.7));
+    cache.set_network_version(1);
+    assert_eq!(win_rate(cache.get(2, Player::PLAYER1)), Some(0.7));
+
+    //2Playerモードでは思考担当ごとに別の予測を持つ
+    let cache = EvalCache::new(32, true);
+    cache.insert(5, Player::PLAYER1, prediction(0.5));
+    assert_eq!(win_rate(cache.get(5, Player::PLAYER2)), None);
+    cache.insert(5, Player::PLAYER2, prediction(-0.5));
+    assert_eq!(win_rate(cache.get(5, Player::PLAYER1)), Some(0.5));
+    assert_eq!(win_rate(cache.get(5, Player::PLAYER2)), Some(-0.5));
+
+    //容量0ならキャッシュせず、数えもしない
+    let cache = EvalCache::new(0, false);
+    cache.insert(0, Player::PLAYER1, prediction(0.1));
+    assert_eq!(win_rate(cache.get(0, Player::PLAYER1)), None);
+    assert_eq!((cache.hits(), cache.misses()), (0, 0));
+}
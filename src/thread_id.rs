@@ -1,4 +1,7 @@
 
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ThreadID {
     id: usize,
@@ -11,4 +14,19 @@ impl ThreadID {
     pub fn id(&self) -> usize {
         self.id
     }
-}
\ No newline at end of file
+
+    /// seedとスレッドIDから、スレッドごとに別の乱数列を作る。seedがNoneならOSの乱数で初期化する
+    pub fn create_rng(&self, seed: Option<u64>) -> StdRng {
+        match seed {
+            Some(seed) => StdRng::seed_from_u64(splitmix64(seed ^ splitmix64(self.id as u64))),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
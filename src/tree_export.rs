@@ -0,0 +1,213 @@
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::action::Action;
+use crate::constant::MOVE_LEN;
+use crate::evaluator::Evaluator;
+use crate::mcts::{BoardState, Mcts};
+use crate::othello_board::OthelloBoard;
+use crate::othello_game::get_next_state;
+use crate::player::Player;
+use crate::thread_id::ThreadID;
+
+/// 書き出した探索木のノード
+#[derive(Debug, Clone)]
+pub struct TreeNode {
+    /// canonical boardのハッシュ
+    pub hash: u128,
+    /// このノードに至る手。ルートはNone
+    pub action: Option<Action>,
+    pub visits: usize,
+    /// この手を指したプレイヤーから見たQ。ルートは手番側から見たQ
+    pub q: f32,
+    /// 親の事前確率。ルートは1
+    pub prior: f32,
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn label(&self) -> String {
+        self.action
+            .map_or("root".to_string(), |action| action.to_notation())
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph mcts {{").unwrap();
+        writeln!(dot, "  node [shape=box];").unwrap();
+        let mut next_id = 0;
+        self.write_dot(&mut dot, &mut next_id);
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    /// 自分と子孫を書き出し、自分のidを返す
+    fn write_dot(&self, dot: &mut String, next_id: &mut usize) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+        writeln!(
+            dot,
+            "  n{} [label=\"{}\\nhash {:x}\\nN {} Q {:.3} P {:.3}\"];",
+            id,
+            self.label(),
+            self.hash,
+            self.visits,
+            self.q,
+            self.prior
+        )
+        .unwrap();
+        for child in &self.children {
+            let child_id = child.write_dot(dot, next_id);
+            writeln!(dot, "  n{} -> n{};", id, child_id).unwrap();
+        }
+        id
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        self.write_json(&mut json);
+        json
+    }
+
+    fn write_json(&self, json: &mut String) {
+        //u128はJSONの数値で表せないので文字列にする
+        write!(
+            json,
+            "{{\"hash\":\"{:x}\",\"move\":\"{}\",\"visits\":{},\"q\":{},\"prior\":{},\"children\":[",
+            self.hash,
+            self.label(),
+            self.visits,
+            json_number(self.q),
+            json_number(self.prior)
+        )
+        .unwrap();
+        for (i, child) in self.children.iter().enumerate() {
+            if i != 0 {
+                json.push(',');
+            }
+            child.write_json(json);
+        }
+        json.push_str("]}");
+    }
+}
+
+/// NaNや無限大はJSONの数値で表せないのでnullにする
+fn json_number(x: f32) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+impl<'a, E: Evaluator + ?Sized> Mcts<'a, E> {
+    /// ルートから辿れる探索木を書き出す。深さmax_depthまで、訪問回数がmin_visits以上の手のみ
+    pub fn export_tree(
+        &self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        max_depth: usize,
+        min_visits: usize,
+    ) -> TreeNode {
+        let s = unorthodox_board
+            .create_canonical_board(player)
+            .string_representation();
+        let children = self.export_children(unorthodox_board, player, max_depth, min_visits);
+        TreeNode {
+            hash: s,
+            action: None,
+            visits: children.iter().map(|c| c.visits).sum(),
+            q: self.root_q(unorthodox_board, player),
+            prior: 1.0,
+            children,
+        }
+    }
+
+    fn export_children(
+        &self,
+        unorthodox_board: &OthelloBoard,
+        player: Player,
+        depth: usize,
+        min_visits: usize,
+    ) -> Vec<TreeNode> {
+        if depth == 0 {
+            return vec![];
+        }
+        let s = unorthodox_board
+            .create_canonical_board(player)
+            .string_representation();
+        let Some(node_info) = self.node.get(&s) else {
+            return vec![];
+        };
+        let mut children = vec![];
+        for a in 0..MOVE_LEN {
+            let Some(info) = self.node_act.get(&BoardState::new(s, a)) else {
+                continue;
+            };
+            if info.count == 0 || info.count < min_visits {
+                continue;
+            }
+            let action = Action::new(a);
+            let mut next_board = unorthodox_board.clone();
+            get_next_state(&mut next_board, player, action);
+            let next_player = player.other();
+            children.push(TreeNode {
+                hash: next_board
+                    .create_canonical_board(next_player)
+                    .string_representation(),
+                action: Some(action),
+                visits: info.count,
+                q: info.win_rate,
+                prior: node_info.predicted_pi.probs()[a],
+                children: self.export_children(&next_board, next_player, depth - 1, min_visits),
+            });
+        }
+        children.sort_by_key(|c| std::cmp::Reverse(c.visits));
+        children
+    }
+}
+
+#[derive(Default)]
+struct TreeExportSlot {
+    /// (max_depth, min_visits)
+    request: Option<(usize, usize)>,
+    result: Option<TreeNode>,
+}
+
+/// メインスレッドから各スレッドのルートの探索木を要求し、受け取るための場所。
+/// スレッドは次に手を決めたとき、要求があればその探索木を書き出す
+#[derive(Clone)]
+pub struct TreeExportSlots(Arc<Vec<Mutex<TreeExportSlot>>>);
+
+impl TreeExportSlots {
+    pub fn new(len: usize) -> Self {
+        Self(Arc::new(
+            (0..len)
+                .map(|_| Mutex::new(TreeExportSlot::default()))
+                .collect(),
+        ))
+    }
+
+    pub fn request(&self, thread_id: usize, max_depth: usize, min_visits: usize) -> bool {
+        let Some(slot) = self.0.get(thread_id) else {
+            return false;
+        };
+        let mut slot = slot.lock().unwrap();
+        slot.request = Some((max_depth, min_visits));
+        slot.result = None;
+        true
+    }
+
+    pub fn take_request(&self, thread_id: &ThreadID) -> Option<(usize, usize)> {
+        self.0[thread_id.id()].lock().unwrap().request.take()
+    }
+
+    pub fn fulfill(&self, thread_id: &ThreadID, tree: TreeNode) {
+        self.0[thread_id.id()].lock().unwrap().result = Some(tree);
+    }
+
+    /// 要求がまだ満たされていなければNone
+    pub fn get(&self, thread_id: usize) -> Option<TreeNode> {
+        self.0.get(thread_id)?.lock().unwrap().result.clone()
+    }
+}